@@ -53,9 +53,7 @@ impl U128 {
 
     #[inline(always)]
     pub fn from_mul_u64(a: u64, b: u64) -> Self {
-        let mut low = u64_long_mul(a, b);
-        low.higher = low.higher;
-        low
+        u64_long_mul(a, b)
     }
 
     pub fn wrapping_add(&self, other: U128) -> Self {
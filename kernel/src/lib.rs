@@ -1,4 +1,15 @@
 #![cfg_attr(target_arch = "spirv", no_std)]
+// The arithmetic is a close port of ref10 and must stay compilable by rust-gpu,
+// so indexed loops and explicit no-op terms are kept on purpose.
+#![allow(
+    clippy::double_parens,
+    clippy::identity_op,
+    clippy::let_and_return,
+    clippy::manual_memcpy,
+    clippy::needless_range_loop,
+    clippy::unnecessary_cast,
+    clippy::wrong_self_convention
+)]
 
 use glam::UVec3;
 use spirv_std::{glam, spirv};
@@ -21,16 +32,18 @@ fn split(bytes: &[u8; 64]) -> [u8; 32] {
     scalar
 }
 
-type Seed = [u8; 32];
-type PublicKey = [u8; 32];
+pub type Seed = [u8; 32];
+pub type PublicKey = [u8; 32];
 
-fn public_from_seed(seed: Seed) -> PublicKey {
+/// Derives the ed25519 public key for `seed` exactly as the compute shader does.
+///
+/// Also usable on the host as a reference for the GPU implementation.
+pub fn public_from_seed(seed: Seed) -> PublicKey {
     let scalar = {
         let hash_output = sha512::hash(&seed);
         split(&hash_output)
     };
-    let pk = edwards25519::ge_scalarmult_base(&scalar).to_bytes();
-    pk
+    edwards25519::ge_scalarmult_base(&scalar).to_bytes()
 }
 
 #[spirv(compute(threads(64)))]
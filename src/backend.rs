@@ -0,0 +1,159 @@
+use std::time::{Duration, Instant};
+
+use rayon::prelude::*;
+use strum_macros::{Display, EnumString};
+use zeroize::{Zeroize, Zeroizing};
+
+use crate::{
+    cpu::{self, CpuBackend},
+    error::Error,
+    gpu::GpuBackend,
+    miner::Context,
+    stats::DeviceStats,
+    PublicKey, Seed,
+};
+
+/// Times a failed device is set up again before giving up.
+const MAX_RECOVERIES: u32 = 3;
+
+/// Mining this long without failing resets the recovery count.
+const RECOVERY_RESET: Duration = Duration::from_secs(60);
+
+/// How often a paused mining thread checks whether to go on.
+const PAUSE_POLL: Duration = Duration::from_millis(100);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, EnumString, Display)]
+#[strum(ascii_case_insensitive)]
+pub enum Backend {
+    #[strum(serialize = "CPU")]
+    Cpu,
+    #[strum(serialize = "CPU-KERNEL")]
+    CpuKernel,
+    #[strum(serialize = "GPU")]
+    Gpu,
+}
+
+impl Backend {
+    /// Sets up the backend, `batch_size` being in blocks of 64 keys on GPU.
+    pub(crate) fn create(
+        self,
+        batch_size: usize,
+        device: Option<usize>,
+    ) -> Result<Box<dyn KeyBackend>, Error> {
+        Ok(match self {
+            Self::Gpu => Box::new(GpuBackend::new(batch_size * 64, device)?),
+            Self::Cpu => Box::new(CpuBackend::new(cpu::dalek_public_from_seed)),
+            Self::CpuKernel => Box::new(CpuBackend::new(cpu::kernel_public_from_seed)),
+        })
+    }
+}
+
+/// Derives public keys from seeds in batches.
+///
+/// Work is split in [`submit`](Self::submit) and [`collect`](Self::collect)
+/// so a device can derive one batch while the previous one is matched.
+pub(crate) trait KeyBackend {
+    /// Device name shown in stats.
+    fn name(&self) -> String;
+
+    /// Number of seeds in a batch.
+    fn batch_size(&self) -> usize;
+
+    /// Starts deriving public keys of `seeds`.
+    fn submit(&mut self, seeds: &[Seed]) -> Result<(), Error>;
+
+    /// Waits for public keys of the seeds submitted last.
+    fn collect(&mut self, pubkeys: &mut [PublicKey]) -> Result<(), Error>;
+
+    /// Wipes copies of seeds the backend holds.
+    fn clear(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+/// Mines with a backend made by `create` until `context` is stopped,
+/// replacing the backend when its device fails.
+pub(crate) fn drive(
+    create: impl Fn() -> Result<Box<dyn KeyBackend>, Error>,
+    context: &Context,
+) -> Result<(), Error> {
+    let mut backend = create()?;
+    let device = context.stats.register(backend.name());
+    let mut recoveries = 0;
+    loop {
+        let start = Instant::now();
+        match mine(backend.as_mut(), &device, context) {
+            Err(Error::Gpu(e)) => {
+                if start.elapsed() > RECOVERY_RESET {
+                    recoveries = 0;
+                }
+                if recoveries == MAX_RECOVERIES {
+                    return Err(Error::Gpu(e));
+                }
+                recoveries += 1;
+                log::warn!(
+                    "GPU failed: {}, setting it up again ({}/{})",
+                    e,
+                    recoveries,
+                    MAX_RECOVERIES
+                );
+                // Release the old device before opening it again.
+                drop(backend);
+                backend = create()?;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Mines with `backend` until `context` is stopped, its seeds run out or the
+/// backend fails.
+fn mine(
+    backend: &mut dyn KeyBackend,
+    device: &DeviceStats,
+    context: &Context,
+) -> Result<(), Error> {
+    let batch_size = backend.batch_size();
+    let mut source = context.seeds.lock().unwrap();
+    source.start();
+
+    let mut pubkeys: Vec<PublicKey> = vec![[0u8; 32]; batch_size];
+    let mut next_seeds: Zeroizing<Vec<Seed>> = Zeroizing::new(vec![[0u8; 32]; batch_size]);
+    let mut seeds: Zeroizing<Vec<Seed>> = Zeroizing::new(vec![[0u8; 32]; batch_size]);
+
+    let mut next_count = source.fill(&mut next_seeds)?;
+    backend.submit(&next_seeds)?;
+    while next_count > 0 && !context.stopping() {
+        if context.paused() {
+            std::thread::sleep(PAUSE_POLL);
+            continue;
+        }
+        let start_now = Instant::now();
+        backend.collect(&mut pubkeys)?;
+        std::mem::swap(&mut seeds, &mut next_seeds);
+        let count = next_count;
+        next_count = source.fill(&mut next_seeds)?;
+        if next_count > 0 {
+            backend.submit(&next_seeds)?;
+        }
+
+        handle_keypairs(&mut seeds[..count], &pubkeys[..count], context);
+        source.checked(&pubkeys[..count])?;
+        device.add_batch(count as u64, start_now.elapsed());
+    }
+
+    // The last submitted batch is never checked.
+    backend.clear()
+}
+
+/// Checks a batch of keypairs, wiping every seed once it is checked.
+fn handle_keypairs(seeds: &mut [Seed], pubkeys: &[PublicKey], context: &Context) {
+    let patterns = context.patterns.read().unwrap();
+    pubkeys
+        .par_iter()
+        .zip(seeds.par_iter_mut())
+        .for_each(|(pk, seed)| {
+            crate::handler::handle_keypair(seed, pk, &patterns, context);
+            seed.zeroize();
+        });
+}
@@ -0,0 +1,248 @@
+//! `--config` TOML files. Settings given on the command line or through
+//! `YGGLKAN_*` environment variables take precedence over the file.
+
+use std::{fmt::Display, net::SocketAddr, path::Path, path::PathBuf, str::FromStr};
+
+use clap::{parser::ValueSource, ArgMatches};
+use serde::{Deserialize, Serialize};
+use ygglkan::{patterns::MatchTarget, score::Score, stats::Report, Backend, Error};
+
+use crate::{Args, Command};
+
+/// Everything a mining run can be configured with, keyed like the
+/// command line options.
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Config {
+    #[serde(default, with = "as_str")]
+    backend: Option<Backend>,
+    device: Option<usize>,
+    batch_size: Option<usize>,
+
+    regexes: Option<Vec<String>>,
+    patterns: Option<PathBuf>,
+    exclude: Option<Vec<String>>,
+    #[serde(default, with = "as_str")]
+    match_target: Option<MatchTarget>,
+    #[serde(default, with = "as_str")]
+    score: Option<Score>,
+    #[serde(default, with = "as_str")]
+    report: Option<Report>,
+    words: Option<PathBuf>,
+
+    output: Option<PathBuf>,
+    output_dir: Option<PathBuf>,
+    encrypt: Option<bool>,
+    export_dir: Option<PathBuf>,
+
+    max_time: Option<u64>,
+    max_results: Option<u64>,
+
+    stats: Option<bool>,
+    stats_interval: Option<u64>,
+    tui: Option<bool>,
+    metrics_listen: Option<SocketAddr>,
+    control_socket: Option<PathBuf>,
+}
+
+impl Config {
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let contents =
+            std::fs::read_to_string(path).map_err(|e| Error::Io(path.display().to_string(), e))?;
+        toml::from_str(&contents).map_err(|e| Error::Config(format!("{}: {}", path.display(), e)))
+    }
+
+    /// Fills in settings of `args` that `matches` shows were neither given
+    /// on the command line nor in the environment.
+    pub fn apply(self, args: &mut Args, matches: &ArgMatches) {
+        let unset = |id: &str| {
+            matches!(
+                matches.value_source(id),
+                None | Some(ValueSource::DefaultValue)
+            )
+        };
+        macro_rules! fill {
+            ($($field:ident),*) => {$(
+                if let (true, Some(value)) = (unset(stringify!($field)), self.$field) {
+                    args.$field = value.into();
+                }
+            )*};
+        }
+        fill!(
+            backend,
+            device,
+            batch_size,
+            exclude,
+            match_target,
+            score,
+            report,
+            words,
+            encrypt,
+            export_dir,
+            max_time,
+            max_results,
+            stats,
+            stats_interval,
+            tui,
+            metrics_listen,
+            control_socket
+        );
+        // Options that exclude each other are taken as a whole.
+        if unset("regexes") && unset("patterns") {
+            fill!(regexes, patterns);
+        }
+        if unset("output") && unset("output_dir") {
+            fill!(output, output_dir);
+        }
+    }
+
+    /// The settings `args` ends up with.
+    pub fn effective(args: &Args) -> Self {
+        Self {
+            backend: Some(args.backend),
+            device: args.device,
+            batch_size: Some(args.batch_size),
+            regexes: args.regexes.clone(),
+            patterns: args.patterns.clone(),
+            exclude: Some(args.exclude.clone()),
+            match_target: Some(args.match_target),
+            score: Some(args.score),
+            report: Some(args.report),
+            words: args.words.clone(),
+            output: args.output.clone(),
+            output_dir: args.output_dir.clone(),
+            encrypt: Some(args.encrypt),
+            export_dir: args.export_dir.clone(),
+            max_time: args.max_time,
+            max_results: args.max_results,
+            stats: Some(args.stats),
+            stats_interval: Some(args.stats_interval),
+            tui: Some(args.tui),
+            metrics_listen: args.metrics_listen,
+            control_socket: args.control_socket.clone(),
+        }
+    }
+}
+
+/// Rejects options that can't be combined, whichever source they come from.
+pub fn check(args: &Args) -> Result<(), Error> {
+    if args.regexes.is_some() && args.patterns.is_some() {
+        return Err(Error::Config(String::from(
+            "regexes and patterns can't be used together",
+        )));
+    }
+    if args.output.is_some() && args.output_dir.is_some() {
+        return Err(Error::Config(String::from(
+            "output and output-dir can't be used together",
+        )));
+    }
+    if args.encrypt && args.output.is_none() && args.output_dir.is_none() {
+        return Err(Error::Config(String::from(
+            "encrypt needs output or output-dir",
+        )));
+    }
+    // Results only printed would be lost under the dashboard.
+    let worker = matches!(args.command, Some(Command::Worker { .. }));
+    if args.tui && !worker && args.output.is_none() && args.output_dir.is_none() {
+        return Err(Error::Config(String::from(
+            "tui needs output or output-dir",
+        )));
+    }
+    if args.stats_interval == 0 {
+        return Err(Error::Config(String::from(
+            "stats-interval must be at least 1",
+        )));
+    }
+    if args.encrypt && args.export_dir.is_some() {
+        return Err(Error::Config(String::from(
+            "encrypt and export-dir can't be used together",
+        )));
+    }
+    Ok(())
+}
+
+/// Stores values through their `FromStr` and `Display` implementations, the
+/// way the command line takes them.
+mod as_str {
+    use super::*;
+
+    use serde::{de, Deserializer, Serializer};
+
+    pub fn serialize<T: Display, S: Serializer>(
+        value: &Option<T>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match value {
+            Some(value) => serializer.collect_str(value),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, T, D>(deserializer: D) -> Result<Option<T>, D::Error>
+    where
+        T: FromStr,
+        T::Err: Display,
+        D: Deserializer<'de>,
+    {
+        Option::<String>::deserialize(deserializer)?
+            .map(|s| s.parse().map_err(de::Error::custom))
+            .transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::{CommandFactory, FromArgMatches};
+
+    use super::{check, Config};
+    use crate::Args;
+
+    fn args(cli: &[&str], config: &str) -> Result<Args, ygglkan::Error> {
+        let matches = Args::command().get_matches_from(cli);
+        let mut args = Args::from_arg_matches(&matches).unwrap();
+        toml::from_str::<Config>(config)
+            .unwrap()
+            .apply(&mut args, &matches);
+        check(&args)?;
+        Ok(args)
+    }
+
+    #[test]
+    fn test_precedence() {
+        let config = r#"
+backend = "cpu"
+batch-size = 4096
+regexes = ["^200:"]
+output = "results.txt"
+report = "top:3"
+"#;
+        let args = args(&["ygglkan", "-b", "2048", "--output-dir", "out"], config).unwrap();
+        assert_eq!(args.backend, ygglkan::Backend::Cpu);
+        assert_eq!(args.batch_size, 2048);
+        assert_eq!(args.regexes, Some(vec![String::from("^200:")]));
+        assert_eq!(args.output, None);
+        assert_eq!(args.output_dir, Some("out".into()));
+        assert_eq!(args.report.to_string(), "top:3");
+
+        let dumped = toml::to_string(&Config::effective(&args)).unwrap();
+        assert!(dumped.contains("backend = \"CPU\""), "{}", dumped);
+        assert!(dumped.contains("report = \"top:3\""), "{}", dumped);
+    }
+
+    #[test]
+    fn test_invalid() {
+        assert!(toml::from_str::<Config>("batch = 1").is_err());
+        assert!(toml::from_str::<Config>("report = \"worst\"").is_err());
+        assert!(args(
+            &["ygglkan", "--export-dir", "keys"],
+            "encrypt = true\noutput = \"a\""
+        )
+        .is_err());
+        assert!(args(&["ygglkan", "--tui"], "").is_err());
+        assert!(args(&["ygglkan"], "stats-interval = 0").is_err());
+        assert!(Args::command()
+            .try_get_matches_from(["ygglkan", "--stats-interval", "0"])
+            .is_err());
+        assert!(args(&["ygglkan", "--tui"], "output = \"a\"").is_ok());
+    }
+}
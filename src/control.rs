@@ -0,0 +1,284 @@
+//! Local control socket to change patterns, pause mining and read stats
+//! while a run goes on. Every line sent is a JSON command and gets a JSON
+//! line back:
+//!
+//! ```text
+//! {"command": "list"}
+//! {"command": "add", "regex": "^200:beef", "min_height": 10}
+//! {"command": "retarget", "index": 0, "regex": "^200:cafe"}
+//! {"command": "remove", "index": 1}
+//! {"command": "pause"}
+//! {"command": "resume"}
+//! {"command": "stats"}
+//! ```
+//!
+//! Replies are `{"ok": true, ...}` or `{"ok": false, "error": "..."}`.
+//! Patterns are numbered as `list` shows them, and removing one moves the
+//! ones after it down. Changes apply from the next batch on.
+
+use std::{
+    fs,
+    io::{self, BufRead, BufReader, Write},
+    os::unix::{
+        fs::{DirBuilderExt, FileTypeExt, PermissionsExt},
+        net::{UnixListener, UnixStream},
+    },
+    path::Path,
+    sync::{atomic::Ordering, Arc},
+};
+
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::{
+    error::Error,
+    miner::Miner,
+    patterns::{self, Defaults},
+};
+
+#[derive(Deserialize, Debug)]
+#[serde(tag = "command", rename_all = "snake_case")]
+enum Command {
+    List,
+    Add {
+        regex: String,
+        #[serde(default)]
+        min_height: u8,
+    },
+    /// Searches for `regex` instead, keeping the minimum height unless
+    /// given.
+    Retarget {
+        index: usize,
+        regex: String,
+        min_height: Option<u8>,
+    },
+    Remove {
+        index: usize,
+    },
+    Pause,
+    Resume,
+    Stats,
+}
+
+/// Starts serving commands for `miner` on a Unix socket at `path` that only
+/// the current user can connect to. Added patterns take `defaults`.
+pub fn spawn_server(miner: Arc<Miner>, path: &Path, defaults: Defaults) -> io::Result<()> {
+    // A socket left behind by a run that didn't exit cleanly refuses
+    // connections. One still in use makes binding fail below.
+    let stale = fs::symlink_metadata(path).map_or(false, |m| m.file_type().is_socket())
+        && UnixStream::connect(path).is_err();
+    if stale {
+        fs::remove_file(path)?;
+    }
+    let listener = bind_private(path)?;
+
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    let miner = miner.clone();
+                    std::thread::spawn(move || {
+                        if let Err(e) = serve(stream, &miner, defaults) {
+                            log::warn!("Control connection failed: {}", e);
+                        }
+                    });
+                }
+                Err(e) => log::warn!("Accepting a control connection failed: {}", e),
+            }
+        }
+    });
+    Ok(())
+}
+
+/// Binds a socket at `path` nobody else can connect to, not even between
+/// binding and setting its permissions: it's bound in a new directory only
+/// the user can enter and then linked into place, which fails like binding
+/// if `path` is taken.
+fn bind_private(path: &Path) -> io::Result<UnixListener> {
+    let mut name = path.file_name().unwrap_or_default().to_owned();
+    name.push(format!(".{}.tmp", std::process::id()));
+    let dir = path.with_file_name(name);
+    // Not recursive, so a directory someone else made fails.
+    fs::DirBuilder::new().mode(0o700).create(&dir)?;
+    let bound = dir.join("socket");
+    let result = UnixListener::bind(&bound).and_then(|listener| {
+        fs::set_permissions(&bound, fs::Permissions::from_mode(0o600))?;
+        fs::hard_link(&bound, path)?;
+        Ok(listener)
+    });
+    let _ = fs::remove_file(&bound);
+    fs::remove_dir(&dir)?;
+    result
+}
+
+fn serve(stream: UnixStream, miner: &Miner, defaults: Defaults) -> io::Result<()> {
+    let mut writer = &stream;
+    for line in BufReader::new(&stream).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let reply = match serde_json::from_str(&line) {
+            Ok(command) => handle(command, miner, defaults).unwrap_or_else(failure),
+            Err(e) => failure(format!("bad command: {}", e)),
+        };
+        writeln!(writer, "{}", reply)?;
+    }
+    Ok(())
+}
+
+fn handle(command: Command, miner: &Miner, defaults: Defaults) -> Result<Value, Error> {
+    match command {
+        Command::List => Ok(json!({ "ok": true, "patterns": list(miner) })),
+        Command::Add { regex, min_height } => {
+            let mut pattern = patterns::from_arg(miner.next_pattern_index(), &regex, defaults)?;
+            pattern.min_height = min_height;
+            miner.add_patterns(vec![pattern])?;
+            Ok(json!({ "ok": true }))
+        }
+        Command::Retarget {
+            index,
+            regex,
+            min_height,
+        } => {
+            let old = miner.with_patterns(|patterns| patterns.get(index).map(|p| p.min_height));
+            let mut pattern = patterns::from_arg(miner.next_pattern_index(), &regex, defaults)?;
+            pattern.min_height = min_height.or(old).unwrap_or(0);
+            miner.replace_pattern(index, pattern)?;
+            Ok(json!({ "ok": true }))
+        }
+        Command::Remove { index } => {
+            miner.remove_pattern(index)?;
+            Ok(json!({ "ok": true }))
+        }
+        Command::Pause => {
+            miner.pause();
+            Ok(json!({ "ok": true, "paused": true }))
+        }
+        Command::Resume => {
+            miner.resume();
+            Ok(json!({ "ok": true, "paused": false }))
+        }
+        Command::Stats => Ok(stats(miner)),
+    }
+}
+
+fn list(miner: &Miner) -> Vec<Value> {
+    // Pattern stats only change along with the patterns, which are held.
+    miner.with_patterns(|patterns| {
+        let stats = miner.stats().patterns();
+        patterns
+            .iter()
+            .zip(stats)
+            .enumerate()
+            .map(|(index, (pattern, stats))| {
+                json!({
+                    "index": index,
+                    "name": pattern.name,
+                    "min_height": pattern.min_height,
+                    "score": pattern.score.to_string(),
+                    "output": pattern.output,
+                    "found": stats.found.load(Ordering::Relaxed),
+                    "best_height": stats.best_height.load(Ordering::Relaxed),
+                    "since_found": stats.since_found().map(|d| d.as_secs_f64()),
+                })
+            })
+            .collect()
+    })
+}
+
+fn stats(miner: &Miner) -> Value {
+    let stats = miner.stats();
+    let devices: Vec<Value> = stats
+        .devices()
+        .iter()
+        .map(|d| json!({ "name": d.name, "keys": d.keys.load(Ordering::Relaxed) }))
+        .collect();
+    json!({
+        "ok": true,
+        "uptime": stats.uptime().as_secs_f64(),
+        "keys": stats.keys(),
+        "hashrate": stats.hashrate(),
+        "found": stats.found(),
+        "paused": miner.is_paused(),
+        "stopping": miner.is_stopping(),
+        "devices": devices,
+    })
+}
+
+fn failure(error: impl ToString) -> Value {
+    json!({ "ok": false, "error": error.to_string() })
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::Value;
+
+    use super::{bind_private, handle, Command};
+    use crate::{patterns, Backend, Miner};
+
+    fn send(miner: &Miner, command: &str) -> Value {
+        let command: Command = serde_json::from_str(command).unwrap();
+        handle(command, miner, Default::default()).unwrap_or_else(super::failure)
+    }
+
+    #[test]
+    fn test_commands() {
+        let miner = Miner::builder()
+            .backend(Backend::Cpu)
+            .patterns(patterns::from_args(&[String::from("^201:")], Default::default()).unwrap())
+            .build()
+            .unwrap();
+
+        let reply = send(
+            &miner,
+            r#"{"command": "add", "regex": "^202:", "min_height": 3}"#,
+        );
+        assert_eq!(reply["ok"], true);
+        let reply = send(
+            &miner,
+            r#"{"command": "retarget", "index": 1, "regex": "^203:"}"#,
+        );
+        assert_eq!(reply["ok"], true);
+        let reply = send(&miner, r#"{"command": "list"}"#);
+        let patterns = reply["patterns"].as_array().unwrap();
+        assert_eq!(patterns.len(), 2);
+        assert_eq!(patterns[1]["name"], "^203:");
+        assert_eq!(patterns[1]["min_height"], 3);
+        assert_eq!(patterns[1]["output"], "pattern-1");
+
+        assert_eq!(
+            send(&miner, r#"{"command": "remove", "index": 0}"#)["ok"],
+            true
+        );
+        assert_eq!(
+            send(&miner, r#"{"command": "remove", "index": 1}"#)["ok"],
+            false
+        );
+        let reply = send(&miner, r#"{"command": "add", "regex": "("}"#);
+        assert_eq!(reply["ok"], false);
+        let reply = send(&miner, r#"{"command": "list"}"#);
+        assert_eq!(reply["patterns"][0]["name"], "^203:");
+
+        assert_eq!(send(&miner, r#"{"command": "pause"}"#)["paused"], true);
+        assert!(miner.is_paused());
+        assert_eq!(send(&miner, r#"{"command": "stats"}"#)["paused"], true);
+    }
+
+    #[test]
+    fn test_bind_private() {
+        use std::os::unix::{fs::PermissionsExt, net::UnixStream};
+
+        let path = std::env::temp_dir().join(format!(
+            "ygglkan-test-control-{}-{:016x}",
+            std::process::id(),
+            rand::random::<u64>()
+        ));
+        let _listener = bind_private(&path).unwrap();
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        UnixStream::connect(&path).unwrap();
+        assert!(bind_private(&path).is_err());
+        std::fs::remove_file(path).unwrap();
+    }
+}
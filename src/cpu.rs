@@ -1,39 +1,97 @@
-use std::{
-    sync::atomic::{AtomicU64, AtomicU8, Ordering},
-    time::Instant,
-};
-
-use rand::RngCore;
+use curve25519_dalek::EdwardsPoint;
 use rayon::prelude::*;
-use regex::Regex;
-
-pub fn start_cpu(stats: bool, regexes: Vec<Regex>) {
-    let generated = AtomicU64::new(0);
-    let start_now = Instant::now();
-
-    let max_leading_zeros: Vec<AtomicU8> = (0..(regexes.len())).map(|_| AtomicU8::new(0)).collect();
-
-    std::iter::repeat(()).par_bridge().for_each(|_| {
-        let (seed, pk) = gen_kp();
-        crate::handler::handle_keypair(&seed, &pk, &regexes, &max_leading_zeros);
-        if generated.fetch_add(1, Ordering::Relaxed) % 1000000 == 0 {
-            if stats {
-                let time_elapsed = (std::time::Instant::now() - start_now).as_secs_f64();
-                let hashrate =
-                    generated.load(Ordering::Relaxed) as f64 / time_elapsed / 1_000_000.0;
-                println!("Hashrate: {:.3} MH/s", hashrate);
-            }
+use sha2::{Digest, Sha512};
+
+use crate::{backend::KeyBackend, error::Error, PublicKey, Seed};
+
+/// Keys derived per thread in a batch.
+pub(crate) const CHUNK_SIZE: usize = 4096;
+
+/// Derives keys on all CPU cores.
+pub struct CpuBackend {
+    public_from_seed: fn(&Seed) -> PublicKey,
+    pubkeys: Vec<PublicKey>,
+}
+
+impl CpuBackend {
+    pub fn new(public_from_seed: fn(&Seed) -> PublicKey) -> Self {
+        Self {
+            public_from_seed,
+            pubkeys: vec![[0u8; 32]; rayon::current_num_threads() * CHUNK_SIZE],
         }
-    });
+    }
+}
+
+impl KeyBackend for CpuBackend {
+    fn name(&self) -> String {
+        String::from("CPU")
+    }
+
+    fn batch_size(&self) -> usize {
+        self.pubkeys.len()
+    }
+
+    fn submit(&mut self, seeds: &[Seed]) -> Result<(), Error> {
+        let public_from_seed = self.public_from_seed;
+        seeds
+            .par_chunks(CHUNK_SIZE)
+            .zip(self.pubkeys.par_chunks_mut(CHUNK_SIZE))
+            .for_each(|(seeds, pubkeys)| {
+                for (seed, pk) in seeds.iter().zip(pubkeys.iter_mut()) {
+                    *pk = public_from_seed(seed);
+                }
+            });
+        Ok(())
+    }
+
+    fn collect(&mut self, pubkeys: &mut [PublicKey]) -> Result<(), Error> {
+        pubkeys.copy_from_slice(&self.pubkeys);
+        Ok(())
+    }
+}
+
+/// Public key derivation through curve25519-dalek.
+///
+/// Skips the `SigningKey` construction and multiplies the clamped scalar
+/// directly with the precomputed basepoint table.
+pub fn dalek_public_from_seed(seed: &Seed) -> PublicKey {
+    let hash = Sha512::digest(seed);
+    let mut scalar = [0u8; 32];
+    scalar.copy_from_slice(&hash[..32]);
+    EdwardsPoint::mul_base_clamped(scalar).compress().0
+}
+
+/// Public key derivation through the shader code compiled for the host.
+pub fn kernel_public_from_seed(seed: &Seed) -> PublicKey {
+    kernel::public_from_seed(*seed)
 }
 
-fn gen_kp() -> (crate::Seed, crate::PublicKey) {
-    let mut seed = [0u8; 32];
-    rand::thread_rng().fill_bytes(&mut seed);
+#[cfg(test)]
+mod tests {
+    use rand::RngCore;
 
-    let kp = ed25519_dalek::SigningKey::from_bytes(&seed);
-    let binding = kp.verifying_key();
-    let public = binding.as_bytes();
+    use super::{dalek_public_from_seed, kernel_public_from_seed};
 
-    (seed, *public)
+    #[test]
+    fn test_kernel_matches_dalek() {
+        let mut seed = [0u8; 32];
+        for _ in 0..64 {
+            rand::thread_rng().fill_bytes(&mut seed);
+            let expected = *ed25519_dalek::SigningKey::from_bytes(&seed)
+                .verifying_key()
+                .as_bytes();
+            assert_eq!(
+                dalek_public_from_seed(&seed),
+                expected,
+                "seed: {}",
+                hex::encode(seed)
+            );
+            assert_eq!(
+                kernel_public_from_seed(&seed),
+                expected,
+                "seed: {}",
+                hex::encode(seed)
+            );
+        }
+    }
 }
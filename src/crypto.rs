@@ -0,0 +1,228 @@
+use std::collections::HashMap;
+
+use argon2::Argon2;
+use chacha20poly1305::{
+    aead::{Aead, KeyInit},
+    Key, XChaCha20Poly1305, XNonce,
+};
+use hkdf::Hkdf;
+use rand::RngCore;
+use sha2::Sha256;
+use zeroize::Zeroizing;
+
+use crate::error::{self, Error};
+
+/// Marks an encrypted record in a results file.
+const PREFIX: &str = "ygglkan-encrypted-v1";
+
+/// Environment variable the passphrase is taken from instead of a prompt.
+const PASSPHRASE_ENV: &str = "YGGLKAN_PASSPHRASE";
+
+pub(crate) type Salt = [u8; 16];
+
+/// Encrypts records with XChaCha20-Poly1305 under an Argon2id key.
+///
+/// The key is derived once per salt, so a mining session encrypts all of its
+/// records with the same salt and a fresh random nonce each.
+pub struct Cipher {
+    salt: Salt,
+    aead: XChaCha20Poly1305,
+}
+
+impl Cipher {
+    pub fn new(passphrase: &str) -> Self {
+        let mut salt = [0u8; 16];
+        rand::thread_rng().fill_bytes(&mut salt);
+        Self::with_salt(passphrase, salt)
+    }
+
+    pub(crate) fn with_salt(passphrase: &str, salt: Salt) -> Self {
+        let key = stretch(passphrase, &salt);
+        Self::with_key(&key, salt)
+    }
+
+    fn with_key(key: &[u8; 32], salt: Salt) -> Self {
+        let aead = XChaCha20Poly1305::new(Key::from_slice(key));
+        Self { salt, aead }
+    }
+
+    /// Encrypts `record` into a single line.
+    pub fn encrypt(&self, record: &str) -> String {
+        let mut nonce = [0u8; 24];
+        rand::thread_rng().fill_bytes(&mut nonce);
+        let ciphertext = self
+            .aead
+            .encrypt(XNonce::from_slice(&nonce), record.as_bytes())
+            .expect("Encryption of in-memory data doesn't fail");
+        format!(
+            "{}:{}:{}:{}\n",
+            PREFIX,
+            hex::encode(self.salt),
+            hex::encode(nonce),
+            hex::encode(ciphertext)
+        )
+    }
+
+    /// Decrypts a line this cipher encrypted.
+    pub(crate) fn decrypt(&self, line: &str) -> Result<Zeroizing<String>, String> {
+        let (salt, nonce, ciphertext) = parse(line)?;
+        if salt != self.salt {
+            return Err(String::from("record encrypted with another salt"));
+        }
+        self.open(&nonce, &ciphertext)
+    }
+
+    fn open(&self, nonce: &[u8; 24], ciphertext: &[u8]) -> Result<Zeroizing<String>, String> {
+        let plaintext = self
+            .aead
+            .decrypt(XNonce::from_slice(nonce), ciphertext)
+            .map_err(|_| String::from("wrong passphrase or corrupted record"))?;
+        String::from_utf8(plaintext)
+            .map(Zeroizing::new)
+            .map_err(|e| e.to_string())
+    }
+}
+
+/// Derives an Argon2id key from `passphrase`.
+fn stretch(passphrase: &str, salt: &[u8]) -> Zeroizing<[u8; 32]> {
+    let mut key = Zeroizing::new([0u8; 32]);
+    Argon2::default()
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key[..])
+        .expect("Argon2 parameters are valid");
+    key
+}
+
+/// A passphrase run through Argon2id once, from which ciphers for many salts
+/// are derived with HKDF-SHA256. For salts picked per connection, where
+/// running Argon2id for each would let anyone connecting use up the CPU.
+pub(crate) struct SessionKey {
+    key: Zeroizing<[u8; 32]>,
+}
+
+impl SessionKey {
+    /// `context` of at least 8 bytes tells apart keys of different uses.
+    pub(crate) fn new(passphrase: &str, context: &str) -> Self {
+        Self {
+            key: stretch(passphrase, context.as_bytes()),
+        }
+    }
+
+    pub(crate) fn cipher(&self, salt: Salt) -> Cipher {
+        let mut key = Zeroizing::new([0u8; 32]);
+        Hkdf::<Sha256>::new(Some(&salt), &self.key[..])
+            .expand(&[], &mut key[..])
+            .expect("32 bytes is a valid HKDF-SHA256 length");
+        Cipher::with_key(&key, salt)
+    }
+}
+
+/// Splits an encrypted line into salt, nonce and ciphertext.
+fn parse(line: &str) -> Result<(Salt, [u8; 24], Vec<u8>), String> {
+    let mut parts = line.trim_end().split(':');
+    let (Some(PREFIX), Some(salt), Some(nonce), Some(ciphertext), None) = (
+        parts.next(),
+        parts.next(),
+        parts.next(),
+        parts.next(),
+        parts.next(),
+    ) else {
+        return Err(String::from("malformed encrypted record"));
+    };
+
+    let salt: Salt = hex::FromHex::from_hex(salt).map_err(|e| format!("salt: {e}"))?;
+    let nonce: [u8; 24] = hex::FromHex::from_hex(nonce).map_err(|e| format!("nonce: {e}"))?;
+    let ciphertext = hex::decode(ciphertext).map_err(|e| format!("ciphertext: {e}"))?;
+    Ok((salt, nonce, ciphertext))
+}
+
+pub fn is_encrypted(line: &str) -> bool {
+    line.starts_with(PREFIX)
+}
+
+/// Decrypts lines written by [`Cipher::encrypt`], deriving a key for every
+/// distinct salt once.
+pub struct Decryptor {
+    passphrase: Zeroizing<String>,
+    ciphers: HashMap<Salt, Cipher>,
+}
+
+impl Decryptor {
+    pub fn new(passphrase: Zeroizing<String>) -> Self {
+        Self {
+            passphrase,
+            ciphers: HashMap::new(),
+        }
+    }
+
+    pub fn decrypt(&mut self, line: &str) -> Result<Zeroizing<String>, String> {
+        let (salt, nonce, ciphertext) = parse(line)?;
+        let passphrase = &self.passphrase;
+        let cipher = self
+            .ciphers
+            .entry(salt)
+            .or_insert_with(|| Cipher::with_salt(passphrase, salt));
+        cipher.open(&nonce, &ciphertext)
+    }
+}
+
+/// Reads the passphrase from the environment or asks for it on the terminal.
+pub fn read_passphrase(confirm: bool) -> Result<Zeroizing<String>, Error> {
+    if let Ok(passphrase) = std::env::var(PASSPHRASE_ENV) {
+        return Ok(Zeroizing::new(passphrase));
+    }
+    let passphrase =
+        Zeroizing::new(rpassword::prompt_password("Passphrase: ").map_err(error::io("terminal"))?);
+    if confirm {
+        let again = Zeroizing::new(
+            rpassword::prompt_password("Repeat passphrase: ").map_err(error::io("terminal"))?,
+        );
+        if again != passphrase {
+            return Err(Error::Key(String::from("passphrases don't match")));
+        }
+    }
+    Ok(passphrase)
+}
+
+#[cfg(test)]
+mod tests {
+    use zeroize::Zeroizing;
+
+    use super::{is_encrypted, Cipher, Decryptor, SessionKey};
+
+    #[test]
+    fn test_round_trip() {
+        let record = "PublicKey: 00\nAddress: 200::\n";
+        let cipher = Cipher::new("secret");
+        let first = cipher.encrypt(record);
+        let second = cipher.encrypt(record);
+        assert!(is_encrypted(&first));
+        assert!(!first.contains("200::"));
+        assert_ne!(first, second);
+
+        let mut decryptor = Decryptor::new(Zeroizing::new(String::from("secret")));
+        assert_eq!(*decryptor.decrypt(&first).unwrap(), record);
+        assert_eq!(*decryptor.decrypt(&second).unwrap(), record);
+
+        let other = Cipher::new("secret").encrypt(record);
+        assert_eq!(*decryptor.decrypt(&other).unwrap(), record);
+    }
+
+    #[test]
+    fn test_wrong_passphrase() {
+        let line = Cipher::new("secret").encrypt("record");
+        assert!(Decryptor::new(Zeroizing::new(String::from("wrong")))
+            .decrypt(&line)
+            .is_err());
+    }
+
+    #[test]
+    fn test_session_key() {
+        let key = SessionKey::new("token", "test context");
+        let line = key.cipher([1; 16]).encrypt("message");
+        let same = SessionKey::new("token", "test context").cipher([1; 16]);
+        assert_eq!(*same.decrypt(&line).unwrap(), "message");
+
+        let other_context = SessionKey::new("token", "other context").cipher([1; 16]);
+        assert!(other_context.decrypt(&line).is_err());
+    }
+}
@@ -0,0 +1,988 @@
+//! Mining on several machines. A [`Coordinator`] hands out numbered
+//! [units](crate::units) of seed counters under a master secret to
+//! [`Worker`]s, checks and saves the hits they send back and spot checks the
+//! records of the units they finish.
+//!
+//! Workers authenticate with a token shared with the coordinator. Messages
+//! are lines encrypted like results file records, under a key derived from
+//! the token once and from that for each connection with a salt the
+//! coordinator picks, and are numbered so they can't be replayed or
+//! reordered. Every message of a worker gets one reply:
+//!
+//! - `hello NAME`: `welcome SECRET`, the master secret in hex
+//! - `next`: `unit INDEX START END` or `stop`
+//! - `done COMPLETION`: `ok`, `stop`, `rejected` or `check SLICE...`, asking
+//!   for `proof LEAF:SIBLING,...` for every slice, answered like `done`
+//! - `hit SEED`: `ok` or `stop`
+//! - `keys COUNT`: `ok` or `stop`, with keys checked since the last report
+
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt::Write as _,
+    fs::File,
+    io::{self, BufRead, BufReader, Read, Write},
+    net::{TcpListener, TcpStream},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use hex::{FromHex, ToHex};
+use rand::RngCore;
+use zeroize::Zeroizing;
+
+use crate::{
+    crypto::{Cipher, Salt, SessionKey},
+    error::{self, Error},
+    miner::{Hit, Miner, ResultSink},
+    seeds::{MasterSecret, RangeSeeds, SeedSource, WorkRange},
+    stats::{DeviceStats, Stats},
+    units::{self, Completion, Digest, Recorder, WorkUnit},
+    PublicKey, Seed,
+};
+
+/// First line the coordinator sends, followed by the salt of the connection.
+const GREETING: &str = "ygglkan-coordinator-v2";
+
+/// Longest line read from a peer, far more than a proof of 100 slices takes.
+const MAX_LINE: u64 = 1 << 20;
+
+/// Failed connection attempts after which a worker gives up.
+const MAX_ATTEMPTS: u32 = 12;
+
+/// Time between connection attempts of a worker.
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+/// Connections a coordinator authenticates at once, each on its own thread.
+const MAX_HANDSHAKES: usize = 64;
+
+/// Time a connection has to authenticate.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Time a stopping coordinator waits for workers to hear about it.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Time after which a coordinator hangs up on a silent worker, unless set.
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Encrypted, numbered messages over a TCP connection.
+struct Channel {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+    cipher: Cipher,
+    sent: u64,
+    received: u64,
+}
+
+impl Channel {
+    fn new(reader: BufReader<TcpStream>, writer: TcpStream, cipher: Cipher) -> Self {
+        Self {
+            reader,
+            writer,
+            cipher,
+            sent: 0,
+            received: 0,
+        }
+    }
+
+    fn send(&mut self, message: &str) -> io::Result<()> {
+        let message = Zeroizing::new(format!("{} {}", self.sent, message));
+        self.writer
+            .write_all(self.cipher.encrypt(&message).as_bytes())?;
+        self.sent += 1;
+        Ok(())
+    }
+
+    fn receive(&mut self) -> io::Result<Zeroizing<String>> {
+        let mut line = String::new();
+        if read_line(&mut self.reader, &mut line)? == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        let message = self.cipher.decrypt(&line).map_err(invalid)?;
+        let (number, message) = message
+            .split_once(' ')
+            .ok_or_else(|| invalid("malformed message"))?;
+        if number != self.received.to_string() {
+            return Err(invalid("message out of order"));
+        }
+        self.received += 1;
+        Ok(Zeroizing::new(message.to_owned()))
+    }
+}
+
+/// Reads a line of at most [`MAX_LINE`] bytes, so a peer can't make it grow
+/// until memory runs out.
+fn read_line(reader: &mut impl BufRead, line: &mut String) -> io::Result<usize> {
+    let read = reader.take(MAX_LINE).read_line(line)?;
+    if read as u64 == MAX_LINE && !line.ends_with('\n') {
+        return Err(invalid("line too long"));
+    }
+    Ok(read)
+}
+
+/// Whether the peer is gone: it closed the connection or stayed silent past
+/// the read timeout, which fails with `WouldBlock` on Unix.
+fn hung_up(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::UnexpectedEof | io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+    )
+}
+
+fn invalid(e: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
+
+/// Hands out work to workers and checks their hits with its miner, whose
+/// patterns, sink and stop conditions apply to all of them. The miner
+/// itself isn't run.
+pub struct Coordinator {
+    miner: Arc<Miner>,
+    key: SessionKey,
+    secret: Zeroizing<MasterSecret>,
+    unit_size: u64,
+    units: Mutex<Units>,
+    spot_checks: usize,
+    timeout: Duration,
+    /// Where completion records are appended.
+    records: Option<Mutex<File>>,
+    /// Stats of every worker name, kept across reconnections.
+    devices: Mutex<HashMap<String, Arc<DeviceStats>>>,
+    /// Connections authenticating.
+    handshakes: AtomicUsize,
+    /// Authenticated connections.
+    workers: AtomicUsize,
+    /// Whether workers connecting and leaving are printed.
+    print: bool,
+}
+
+/// Units not handed out yet.
+#[derive(Default)]
+struct Units {
+    next: u64,
+    /// Units of workers that left or failed before finishing them.
+    returned: Vec<WorkUnit>,
+}
+
+impl Coordinator {
+    /// Coordinates work under a fresh master secret, in units of
+    /// `unit_size` seeds, spot checking one slice of every finished unit.
+    pub fn new(miner: Arc<Miner>, token: &str, unit_size: u64) -> Self {
+        let mut secret = Zeroizing::new([0u8; 32]);
+        rand::thread_rng().fill_bytes(&mut secret[..]);
+        Self {
+            miner,
+            key: SessionKey::new(token, GREETING),
+            secret,
+            unit_size,
+            units: Mutex::new(Units::default()),
+            spot_checks: 1,
+            timeout: DEFAULT_TIMEOUT,
+            records: None,
+            devices: Mutex::new(HashMap::new()),
+            handshakes: AtomicUsize::new(0),
+            workers: AtomicUsize::new(0),
+            print: true,
+        }
+    }
+
+    /// Slices of every finished unit recomputed on the CPU, 0 to trust
+    /// workers.
+    pub fn spot_checks(mut self, slices: usize) -> Self {
+        self.spot_checks = slices;
+        self
+    }
+
+    /// Hangs up on workers that send nothing for `timeout`, handing out
+    /// their units again. Workers report keys every stats interval, so a few
+    /// of those are enough.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Only logs workers connecting and leaving, for when the terminal shows
+    /// something else.
+    pub fn quiet(mut self) -> Self {
+        self.print = false;
+        self
+    }
+
+    /// Appends the record of every finished unit to `file`.
+    pub fn records(mut self, file: File) -> Self {
+        self.records = Some(Mutex::new(file));
+        self
+    }
+
+    /// Serves workers on `listener` until the miner stops and connected
+    /// workers were told so.
+    pub fn serve(self: Arc<Self>, listener: TcpListener) {
+        let coordinator = self.clone();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                match stream {
+                    Ok(_) if coordinator.handshakes.load(Ordering::Relaxed) >= MAX_HANDSHAKES => {
+                        log::warn!("Refusing a worker, {} are authenticating", MAX_HANDSHAKES)
+                    }
+                    Ok(stream) => {
+                        // Counted here, so that only this thread adds to it.
+                        coordinator.handshakes.fetch_add(1, Ordering::Relaxed);
+                        let coordinator = coordinator.clone();
+                        std::thread::spawn(move || coordinator.handle(stream));
+                    }
+                    Err(e) => log::warn!("Accepting a worker failed: {}", e),
+                }
+            }
+        });
+
+        while !self.miner.is_stopping() {
+            std::thread::sleep(Duration::from_millis(100));
+        }
+        let deadline = Instant::now() + SHUTDOWN_TIMEOUT;
+        while self.workers.load(Ordering::Relaxed) > 0 && Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(100));
+        }
+    }
+
+    /// A unit to hand out, `None` once all counters were.
+    fn allocate(&self) -> Option<WorkUnit> {
+        let mut units = self.units.lock().unwrap();
+        if let Some(unit) = units.returned.pop() {
+            return Some(unit);
+        }
+        let unit = WorkUnit::new(units.next, self.unit_size)?;
+        units.next += 1;
+        Some(unit)
+    }
+
+    fn device(&self, name: &str) -> Arc<DeviceStats> {
+        let mut devices = self.devices.lock().unwrap();
+        let device = devices
+            .entry(name.to_owned())
+            .or_insert_with(|| self.miner.stats().register(name));
+        device.clone()
+    }
+
+    fn handle(&self, stream: TcpStream) {
+        let peer = match stream.peer_addr() {
+            Ok(addr) => addr.to_string(),
+            Err(_) => String::from("unknown"),
+        };
+        let handshake = self.handshake(stream);
+        self.handshakes.fetch_sub(1, Ordering::Relaxed);
+        let (mut channel, name) = match handshake {
+            Ok(authenticated) => authenticated,
+            Err(e) => {
+                log::warn!("Worker at {} failed to authenticate: {}", peer, e);
+                return;
+            }
+        };
+        self.workers.fetch_add(1, Ordering::Relaxed);
+        match self.print {
+            true => println!("Worker {} connected from {}", name, peer),
+            false => log::info!("Worker {} connected from {}", name, peer),
+        }
+
+        let mut units = Vec::new();
+        match self.serve_worker(&mut channel, &name, &mut units) {
+            Err(e) if hung_up(&e) && self.print => println!("Worker at {} disconnected", peer),
+            Err(e) if hung_up(&e) => log::info!("Worker at {} disconnected", peer),
+            Err(e) => log::warn!("Worker at {} failed: {}", peer, e),
+            Ok(()) => {}
+        }
+        self.units.lock().unwrap().returned.extend(units);
+        self.workers.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    /// Authenticates a worker within [`HANDSHAKE_TIMEOUT`], returning the
+    /// connection and the worker's name.
+    fn handshake(&self, stream: TcpStream) -> io::Result<(Channel, String)> {
+        let mut salt: Salt = [0u8; 16];
+        rand::thread_rng().fill_bytes(&mut salt);
+        stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+        stream.set_write_timeout(Some(HANDSHAKE_TIMEOUT))?;
+        let mut writer = stream.try_clone()?;
+        writeln!(writer, "{} {}", GREETING, hex::encode(salt))?;
+        let cipher = self.key.cipher(salt);
+        let mut channel = Channel::new(BufReader::new(stream), writer, cipher);
+
+        let hello = channel.receive()?;
+        let name = hello
+            .strip_prefix("hello ")
+            .ok_or_else(|| invalid("expected hello"))?
+            .to_owned();
+        let welcome = Zeroizing::new(format!("welcome {}", self.secret.encode_hex::<String>()));
+        channel.send(&welcome)?;
+
+        channel.writer.set_read_timeout(Some(self.timeout))?;
+        channel.writer.set_write_timeout(Some(self.timeout))?;
+        Ok((channel, name))
+    }
+
+    /// Answers messages of worker `name`, keeping the units it works on in
+    /// `units`.
+    fn serve_worker(
+        &self,
+        channel: &mut Channel,
+        name: &str,
+        units: &mut Vec<WorkUnit>,
+    ) -> io::Result<()> {
+        let device = self.device(name);
+        let mut last = Instant::now();
+        loop {
+            let message = channel.receive()?;
+            let (command, argument) = message.split_once(' ').unwrap_or((message.as_str(), ""));
+            let reply = match command {
+                "next" => {
+                    let unit = match self.miner.is_stopping() {
+                        true => None,
+                        false => self.allocate(),
+                    };
+                    match unit {
+                        Some(unit) => {
+                            units.push(unit);
+                            format!(
+                                "unit {} {} {}",
+                                unit.index, unit.range.start, unit.range.end
+                            )
+                        }
+                        None => String::from("stop"),
+                    }
+                }
+                "done" => {
+                    let completion: Completion = argument.parse().map_err(invalid)?;
+                    let position = units
+                        .iter()
+                        .position(|unit| unit.index == completion.unit)
+                        .ok_or_else(|| {
+                            invalid(format!("unit {} isn't handed out", completion.unit))
+                        })?;
+                    // Kept until audited, so a connection lost meanwhile
+                    // returns it.
+                    let unit = units[position];
+                    let audit = self.audit(channel, &unit, &completion)?;
+                    units.swap_remove(position);
+                    match audit {
+                        Some(checked) => {
+                            self.record(&unit, &completion, name, checked);
+                            self.status()
+                        }
+                        None => {
+                            log::error!(
+                                "Unit {} of worker {} failed its spot check, handing it out again",
+                                unit.index,
+                                name
+                            );
+                            self.units.lock().unwrap().returned.push(unit);
+                            String::from("rejected")
+                        }
+                    }
+                }
+                "hit" => {
+                    let seed = Zeroizing::new(Seed::from_hex(argument).map_err(invalid)?);
+                    self.miner.check(&seed);
+                    self.status()
+                }
+                "keys" => {
+                    let keys = argument.parse().map_err(invalid)?;
+                    device.add_batch(keys, last.elapsed());
+                    last = Instant::now();
+                    self.status()
+                }
+                _ => return Err(invalid(format!("unknown message {}", command))),
+            };
+            channel.send(&reply)?;
+        }
+    }
+
+    /// Checks `completion` of `unit`, spot checking random slices. Returns
+    /// the slices checked, or `None` if the record is wrong.
+    fn audit(
+        &self,
+        channel: &mut Channel,
+        unit: &WorkUnit,
+        completion: &Completion,
+    ) -> io::Result<Option<usize>> {
+        if completion.keys != unit.range.len() {
+            return Ok(None);
+        }
+        let count = self.spot_checks.min(unit.slices());
+        if count == 0 {
+            return Ok(Some(0));
+        }
+        let slices = rand::seq::index::sample(&mut rand::thread_rng(), unit.slices(), count);
+        let mut request = String::from("check");
+        for slice in slices.iter() {
+            write!(request, " {}", slice).unwrap();
+        }
+        channel.send(&request)?;
+
+        let message = channel.receive()?;
+        let proofs = message
+            .strip_prefix("proof ")
+            .ok_or_else(|| invalid("expected proof"))?;
+        let mut proofs = proofs.split(' ');
+        for slice in slices.iter() {
+            let proof = proofs.next().ok_or_else(|| invalid("missing proof"))?;
+            if !self.verify_slice(unit, completion, slice, proof) {
+                return Ok(None);
+            }
+        }
+        Ok(Some(count))
+    }
+
+    /// Recomputes slice `slice` of `unit` and checks it against `proof`.
+    fn verify_slice(
+        &self,
+        unit: &WorkUnit,
+        completion: &Completion,
+        slice: usize,
+        proof: &str,
+    ) -> bool {
+        let Some((leaf, path)) = parse_proof(proof) else {
+            return false;
+        };
+        let (expected, best_height) = units::recompute(&self.secret, unit, slice);
+        leaf == expected
+            && best_height <= completion.best_height
+            && units::verify(&completion.root, unit.slices(), slice, &leaf, &path)
+    }
+
+    fn record(&self, unit: &WorkUnit, completion: &Completion, worker: &str, checked: usize) {
+        let Some(records) = &self.records else {
+            return;
+        };
+        let result = writeln!(
+            records.lock().unwrap(),
+            "unit={} range={}..{} keys={} best={} root={} worker={} checked={}",
+            unit.index,
+            unit.range.start,
+            unit.range.end,
+            completion.keys,
+            completion.best_height,
+            completion.root.encode_hex::<String>(),
+            worker,
+            checked
+        );
+        if let Err(e) = result {
+            log::error!("Writing the record of unit {} failed: {}", unit.index, e);
+        }
+    }
+
+    fn status(&self) -> String {
+        match self.miner.is_stopping() {
+            true => String::from("stop"),
+            false => String::from("ok"),
+        }
+    }
+}
+
+/// A worker's connection to its coordinator, opened again when it fails.
+pub struct Worker {
+    addr: String,
+    key: SessionKey,
+    name: String,
+    session: Mutex<Session>,
+    /// Set once the coordinator stops handing out work.
+    stopped: AtomicBool,
+}
+
+struct Session {
+    channel: Option<Channel>,
+    /// Counts connections; units of earlier ones are handed out again.
+    connection: u64,
+    secret: Zeroizing<MasterSecret>,
+}
+
+/// A reply of the coordinator and the connection it came from.
+struct Reply {
+    text: Zeroizing<String>,
+    connection: u64,
+    secret: Zeroizing<MasterSecret>,
+}
+
+impl Worker {
+    /// Connects to the coordinator at `addr`, introducing the worker as
+    /// `name`.
+    pub fn connect(addr: &str, token: &str, name: &str) -> Result<Arc<Self>, Error> {
+        let key = SessionKey::new(token, GREETING);
+        let (channel, secret) = open(addr, &key, name)?;
+        Ok(Arc::new(Self {
+            addr: addr.to_owned(),
+            key,
+            name: name.to_owned(),
+            session: Mutex::new(Session {
+                channel: Some(channel),
+                connection: 0,
+                secret,
+            }),
+            stopped: AtomicBool::new(false),
+        }))
+    }
+
+    /// Seeds of the units the coordinator hands out.
+    pub fn seeds(self: &Arc<Self>) -> WorkerSeeds {
+        WorkerSeeds {
+            worker: self.clone(),
+            units: VecDeque::new(),
+            pending: VecDeque::new(),
+        }
+    }
+
+    /// Sends hits to the coordinator, which checks and saves them, printing
+    /// them if `print`.
+    pub fn sink(self: &Arc<Self>, print: bool) -> impl ResultSink {
+        let worker = self.clone();
+        move |hit: &Hit| -> Result<(), Error> {
+            let message = Zeroizing::new(format!("hit {}", hit.seed.encode_hex::<String>()));
+            worker.notify(&message)?;
+            if !print {
+                return Ok(());
+            }
+            println!(
+                "Found {} (height {}) for {}, sent to coordinator",
+                hit.address, hit.height, hit.pattern.name
+            );
+            Ok(())
+        }
+    }
+
+    /// Reports the keys counted by `stats` every `interval` until the
+    /// coordinator stops handing out work.
+    pub fn spawn_reporter(self: &Arc<Self>, stats: Arc<Stats>, interval: Duration) {
+        let worker = self.clone();
+        std::thread::spawn(move || {
+            let mut reported = 0;
+            while !worker.stopped.load(Ordering::Relaxed) {
+                std::thread::sleep(interval);
+                let keys = stats.keys();
+                match worker.notify(&format!("keys {}", keys - reported)) {
+                    Ok(()) => reported = keys,
+                    Err(e) => {
+                        log::error!("Reporting to the coordinator failed: {}", e);
+                        worker.stopped.store(true, Ordering::Relaxed);
+                    }
+                }
+            }
+        });
+    }
+
+    /// Sends `message` and waits for the reply, connecting again if the
+    /// connection fails. Other requests aren't held up while waiting to.
+    fn request(&self, message: &str) -> Result<Reply, Error> {
+        let mut attempts = 0;
+        loop {
+            let mut guard = self.session.lock().unwrap();
+            let session = &mut *guard;
+            let error = match &mut session.channel {
+                Some(channel) => match channel.send(message).and_then(|_| channel.receive()) {
+                    Ok(text) => {
+                        return Ok(Reply {
+                            text,
+                            connection: session.connection,
+                            secret: session.secret.clone(),
+                        })
+                    }
+                    Err(e) => Error::Io(format!("coordinator {}", self.addr), e),
+                },
+                None => match open(&self.addr, &self.key, &self.name) {
+                    Ok((channel, secret)) => {
+                        session.channel = Some(channel);
+                        session.connection += 1;
+                        session.secret = secret;
+                        continue;
+                    }
+                    Err(e @ Error::Key(_)) => return Err(e),
+                    Err(e) => e,
+                },
+            };
+            session.channel = None;
+            drop(guard);
+            attempts += 1;
+            if attempts == MAX_ATTEMPTS {
+                return Err(error);
+            }
+            log::warn!("{}, connecting again in {:?}", error, RECONNECT_DELAY);
+            std::thread::sleep(RECONNECT_DELAY);
+        }
+    }
+
+    /// Sends `message` on connection `connection` only, returning `None`
+    /// if it was lost.
+    fn request_on(&self, message: &str, connection: u64) -> Option<Reply> {
+        let mut guard = self.session.lock().unwrap();
+        let session = &mut *guard;
+        if session.connection != connection {
+            return None;
+        }
+        let channel = session.channel.as_mut()?;
+        match channel.send(message).and_then(|_| channel.receive()) {
+            Ok(text) => Some(Reply {
+                text,
+                connection,
+                secret: session.secret.clone(),
+            }),
+            Err(e) => {
+                log::warn!("Connection to coordinator {} failed: {}", self.addr, e);
+                session.channel = None;
+                None
+            }
+        }
+    }
+
+    /// Sends a message answered with `ok` or `stop`.
+    fn notify(&self, message: &str) -> Result<(), Error> {
+        let reply = self.request(message)?;
+        match reply.text.as_str() {
+            "ok" => Ok(()),
+            "stop" => {
+                self.stopped.store(true, Ordering::Relaxed);
+                Ok(())
+            }
+            text => Err(self.unexpected(text)),
+        }
+    }
+
+    /// Reports a finished unit handed out on `connection` and answers its
+    /// spot check. A unit whose connection was lost is dropped, the
+    /// coordinator hands it out again.
+    fn complete(&self, recorder: &Recorder, connection: u64) -> Result<(), Error> {
+        let completion = recorder.completion();
+        let Some(mut reply) = self.request_on(&format!("done {}", completion), connection) else {
+            return Ok(());
+        };
+        let proof = match reply.text.strip_prefix("check ") {
+            Some(slices) => {
+                let mut proof = String::from("proof");
+                for slice in slices.split(' ') {
+                    let (leaf, path) = slice
+                        .parse()
+                        .ok()
+                        .and_then(|slice| recorder.proof(slice))
+                        .ok_or_else(|| self.unexpected(&reply.text))?;
+                    let path: Vec<String> = path.iter().map(hex::encode).collect();
+                    write!(proof, " {}:{}", hex::encode(leaf), path.join(",")).unwrap();
+                }
+                Some(proof)
+            }
+            None => None,
+        };
+        if let Some(proof) = proof {
+            let Some(next) = self.request_on(&proof, connection) else {
+                return Ok(());
+            };
+            reply = next;
+        }
+
+        match reply.text.as_str() {
+            "ok" => Ok(()),
+            "stop" => {
+                self.stopped.store(true, Ordering::Relaxed);
+                Ok(())
+            }
+            "rejected" => Err(Error::Gpu(format!(
+                "unit {} failed the coordinator's spot check",
+                completion.unit
+            ))),
+            text => Err(self.unexpected(text)),
+        }
+    }
+
+    fn unexpected(&self, reply: &str) -> Error {
+        Error::Io(
+            format!("coordinator {}", self.addr),
+            invalid(format!("unexpected reply {}", reply)),
+        )
+    }
+}
+
+/// Connects and authenticates to the coordinator at `addr`, returning the
+/// connection and the master secret.
+fn open(
+    addr: &str,
+    key: &SessionKey,
+    name: &str,
+) -> Result<(Channel, Zeroizing<MasterSecret>), Error> {
+    let what = format!("coordinator {}", addr);
+    let stream = TcpStream::connect(addr).map_err(error::io(&what))?;
+    let mut reader = BufReader::new(stream.try_clone().map_err(error::io(&what))?);
+    let mut greeting = String::new();
+    read_line(&mut reader, &mut greeting).map_err(error::io(&what))?;
+    let salt = match greeting.trim_end().split_once(' ') {
+        Some((GREETING, salt)) => {
+            Salt::from_hex(salt).map_err(|e| Error::Io(what.clone(), invalid(e)))?
+        }
+        _ => return Err(Error::Io(what, invalid("not a ygglkan coordinator"))),
+    };
+
+    let mut channel = Channel::new(reader, stream, key.cipher(salt));
+    channel
+        .send(&format!("hello {}", name))
+        .map_err(error::io(&what))?;
+    // The coordinator hangs up on a wrong token, and a wrong token can't
+    // decrypt its welcome.
+    let welcome = channel.receive().map_err(|e| match e.kind() {
+        io::ErrorKind::UnexpectedEof | io::ErrorKind::InvalidData => {
+            Error::Key(format!("{} rejected the token", what))
+        }
+        _ => Error::Io(what.clone(), e),
+    })?;
+    let secret = welcome
+        .strip_prefix("welcome ")
+        .and_then(|secret| MasterSecret::from_hex(secret).ok())
+        .ok_or_else(|| Error::Io(what, invalid("malformed welcome")))?;
+    Ok((channel, Zeroizing::new(secret)))
+}
+
+/// Parses `LEAF:SIBLING,...` of a slice.
+fn parse_proof(proof: &str) -> Option<(Digest, Vec<Digest>)> {
+    let (leaf, path) = proof.split_once(':')?;
+    let path = match path {
+        "" => Vec::new(),
+        path => path
+            .split(',')
+            .map(|node| Digest::from_hex(node).ok())
+            .collect::<Option<_>>()?,
+    };
+    Some((Digest::from_hex(leaf).ok()?, path))
+}
+
+/// Seeds of the units a coordinator hands out to a [`Worker`], recording
+/// their completion as batches are checked.
+pub struct WorkerSeeds {
+    worker: Arc<Worker>,
+    /// Units being mined, oldest first.
+    units: VecDeque<Active>,
+    /// Connection, unit and size of batches filled and not checked yet.
+    pending: VecDeque<(u64, u64, usize)>,
+}
+
+struct Active {
+    seeds: RangeSeeds,
+    recorder: Recorder,
+    /// Connection the unit was handed out on.
+    connection: u64,
+}
+
+impl SeedSource for WorkerSeeds {
+    fn fill(&mut self, seeds: &mut [Seed]) -> Result<usize, Error> {
+        loop {
+            if self.worker.stopped.load(Ordering::Relaxed) {
+                return Ok(0);
+            }
+            // Units of a lost connection go to other workers.
+            let connection = self.worker.session.lock().unwrap().connection;
+            self.units.retain(|active| active.connection == connection);
+            for active in &mut self.units {
+                let count = active.seeds.fill(seeds)?;
+                if count > 0 {
+                    let unit = active.recorder.unit().index;
+                    self.pending.push_back((connection, unit, count));
+                    return Ok(count);
+                }
+            }
+
+            let reply = self.worker.request("next")?;
+            if reply.text.as_str() == "stop" {
+                self.worker.stopped.store(true, Ordering::Relaxed);
+                return Ok(0);
+            }
+            let unit =
+                parse_unit(&reply.text).ok_or_else(|| self.worker.unexpected(&reply.text))?;
+            self.units.push_back(Active {
+                seeds: RangeSeeds::new(reply.secret, unit.range),
+                recorder: Recorder::new(unit),
+                connection: reply.connection,
+            });
+        }
+    }
+
+    fn checked(&mut self, pubkeys: &[PublicKey]) -> Result<(), Error> {
+        let Some((connection, unit, _)) = self.pending.pop_front() else {
+            return Ok(());
+        };
+        let Some(position) = self.units.iter().position(|active| {
+            active.connection == connection && active.recorder.unit().index == unit
+        }) else {
+            return Ok(());
+        };
+        let active = &mut self.units[position];
+        active.recorder.add(pubkeys);
+        if active.recorder.is_complete() {
+            let active = self.units.remove(position).unwrap();
+            self.worker.complete(&active.recorder, active.connection)?;
+        }
+        Ok(())
+    }
+
+    fn start(&mut self) {
+        // Mine batches lost with the backend again.
+        self.pending.clear();
+        for active in &mut self.units {
+            active.seeds.rewind(active.recorder.next());
+        }
+    }
+}
+
+/// Parses `unit INDEX START END`.
+fn parse_unit(reply: &str) -> Option<WorkUnit> {
+    let mut parts = reply.strip_prefix("unit ")?.split(' ');
+    let unit = WorkUnit {
+        index: parts.next()?.parse().ok()?,
+        range: WorkRange {
+            start: parts.next()?.parse().ok()?,
+            end: parts.next()?.parse().ok()?,
+        },
+    };
+    parts.next().is_none().then_some(unit)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::{Ipv6Addr, TcpListener},
+        sync::{atomic::Ordering, Arc, Mutex},
+        time::Duration,
+    };
+
+    use super::{Coordinator, Worker};
+    use crate::{
+        cpu,
+        crypto::SessionKey,
+        seeds::{self, WorkRange},
+        units::{Recorder, WorkUnit},
+        Backend, Error, Hit, Miner, PublicKey,
+    };
+
+    fn coordinator(found: Arc<Mutex<Vec<Ipv6Addr>>>) -> (Arc<Coordinator>, String) {
+        let miner = Miner::builder()
+            .backend(Backend::Cpu)
+            .stop_after_results(1)
+            .sink(move |hit: &Hit| -> Result<(), Error> {
+                found.lock().unwrap().push(hit.address);
+                Ok(())
+            })
+            .build()
+            .unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let coordinator = Arc::new(Coordinator::new(Arc::new(miner), "token", 1 << 16));
+        let serving = coordinator.clone();
+        std::thread::spawn(move || serving.serve(listener));
+        (coordinator, addr)
+    }
+
+    #[test]
+    fn test_worker() {
+        let found = Arc::new(Mutex::new(Vec::new()));
+        let (_, addr) = coordinator(found.clone());
+
+        let worker = Worker::connect(&addr, "token", "test").unwrap();
+        let miner = Miner::builder()
+            .backend(Backend::Cpu)
+            .seeds(worker.seeds())
+            .sink(worker.sink(true))
+            .build()
+            .unwrap();
+        // Returns once the coordinator has its result and stops the worker.
+        miner.run().unwrap();
+        assert!(!found.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_wrong_token() {
+        let (_, addr) = coordinator(Default::default());
+        assert!(matches!(
+            Worker::connect(&addr, "wrong", "test"),
+            Err(Error::Key(_))
+        ));
+    }
+
+    #[test]
+    fn test_long_line() {
+        let mut line = String::new();
+        let long = vec![b'a'; super::MAX_LINE as usize + 1];
+        assert!(super::read_line(&mut &long[..], &mut line).is_err());
+
+        line.clear();
+        assert_eq!(
+            super::read_line(&mut &b"short\n"[..], &mut line).unwrap(),
+            6
+        );
+        assert_eq!(line, "short\n");
+    }
+
+    #[test]
+    fn test_units() {
+        let (coordinator, _) = coordinator(Default::default());
+        let first = coordinator.allocate().unwrap();
+        assert_eq!(Some(first), WorkUnit::new(0, 1 << 16));
+        assert_eq!(coordinator.allocate().unwrap().range.start, 1 << 16);
+        coordinator.units.lock().unwrap().returned.push(first);
+        assert_eq!(coordinator.allocate(), Some(first));
+        assert_eq!(coordinator.allocate().unwrap().index, 2);
+
+        // Counters run out rather than wrap around.
+        coordinator.units.lock().unwrap().next = u64::MAX / (1 << 16) - 1;
+        assert_eq!(coordinator.allocate().unwrap().range.end, u64::MAX - 0xffff);
+        assert_eq!(coordinator.allocate(), None);
+    }
+
+    #[test]
+    fn test_lost_audit() {
+        let (coordinator, addr) = coordinator(Default::default());
+        let key = SessionKey::new("token", super::GREETING);
+        let (mut channel, _) = super::open(&addr, &key, "test").unwrap();
+        channel.send("next").unwrap();
+        let unit = super::parse_unit(&channel.receive().unwrap()).unwrap();
+        let mut recorder = Recorder::new(unit);
+        recorder.add(&vec![[0; 32]; unit.range.len() as usize]);
+        channel
+            .send(&format!("done {}", recorder.completion()))
+            .unwrap();
+        assert!(channel.receive().unwrap().starts_with("check "));
+
+        // Hanging up instead of sending proofs hands the unit out again.
+        drop(channel);
+        while coordinator.workers.load(Ordering::Relaxed) > 0 {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(coordinator.allocate(), Some(unit));
+    }
+
+    #[test]
+    fn test_spot_check() {
+        let (coordinator, _) = coordinator(Default::default());
+        let unit = WorkUnit {
+            index: 0,
+            range: WorkRange {
+                start: 0,
+                end: 1000,
+            },
+        };
+        let record = |pubkeys: &[PublicKey]| {
+            let mut recorder = Recorder::new(unit);
+            recorder.add(pubkeys);
+            let (leaf, path) = recorder.proof(0).unwrap();
+            assert!(path.is_empty());
+            (recorder.completion(), format!("{}:", hex::encode(leaf)))
+        };
+
+        let mut pubkeys: Vec<PublicKey> = (0..1000)
+            .map(|counter| {
+                cpu::dalek_public_from_seed(&seeds::derive(&coordinator.secret, counter))
+            })
+            .collect();
+        let (completion, proof) = record(&pubkeys);
+        assert!(coordinator.verify_slice(&unit, &completion, 0, &proof));
+
+        // A device getting a single key wrong, one that doesn't start with
+        // a zero byte.
+        let wrong = pubkeys.iter().position(|pk| pk[0] != 0).unwrap();
+        pubkeys[wrong][31] ^= 1;
+        let (completion, proof) = record(&pubkeys);
+        assert!(!coordinator.verify_slice(&unit, &completion, 0, &proof));
+    }
+}
@@ -0,0 +1,68 @@
+use std::fmt;
+
+/// Everything that can go wrong while setting up or running the miner.
+#[derive(Debug)]
+pub enum Error {
+    /// No GPU could be found or opened.
+    Device(String),
+    /// The compute shader or pipeline was rejected by the driver.
+    Shader(String),
+    /// The batch doesn't fit the limits of the device.
+    Limits(String),
+    /// The GPU failed while mining, usually because the device was lost.
+    Gpu(String),
+    /// A pattern or pattern file is invalid.
+    Pattern(String),
+    /// The config file or the options it combines with are invalid.
+    Config(String),
+    /// Reading or writing the named file or stream failed.
+    Io(String, std::io::Error),
+    /// A key, passphrase or encrypted record can't be used.
+    Key(String),
+    /// A result sink rejected a hit.
+    Sink(String),
+}
+
+impl Error {
+    /// Process exit code `main` reports this error with.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Self::Sink(_) => 1,
+            Self::Pattern(_) | Self::Config(_) => 2,
+            Self::Io(..) => 3,
+            Self::Key(_) => 4,
+            Self::Device(_) | Self::Shader(_) | Self::Limits(_) => 5,
+            Self::Gpu(_) => 6,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Device(e) => write!(f, "no usable GPU: {}", e),
+            Self::Shader(e) => write!(f, "failed to create compute shader: {}", e),
+            Self::Limits(e) => write!(f, "batch doesn't fit the device: {}", e),
+            Self::Gpu(e) => write!(f, "GPU failed: {}", e),
+            Self::Pattern(e) => write!(f, "invalid pattern: {}", e),
+            Self::Config(e) => write!(f, "invalid config: {}", e),
+            Self::Io(what, e) => write!(f, "{}: {}", what, e),
+            Self::Key(e) | Self::Sink(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(_, e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+/// Wraps I/O errors of accessing `what`, for use with `map_err`.
+pub(crate) fn io(what: impl fmt::Display) -> impl FnOnce(std::io::Error) -> Error {
+    let what = what.to_string();
+    move |e| Error::Io(what, e)
+}
@@ -0,0 +1,186 @@
+use std::{net::Ipv6Addr, str::FromStr};
+
+/// Quantiles printed along with the expected time.
+const QUANTILES: [f64; 3] = [0.5, 0.9, 0.99];
+
+/// IPv6 prefix in `addr/len` notation.
+#[derive(Debug, Clone)]
+pub struct Prefix {
+    addr: Ipv6Addr,
+    len: u8,
+}
+
+impl FromStr for Prefix {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (addr, len) = s
+            .split_once('/')
+            .ok_or_else(|| format!("missing prefix length in {s:?}"))?;
+        let addr = Ipv6Addr::from_str(addr).map_err(|e| format!("{addr:?}: {e}"))?;
+        let len = u8::from_str(len).map_err(|e| format!("{len:?}: {e}"))?;
+        if len > 128 {
+            return Err(format!("prefix length {len} is longer than 128"));
+        }
+        Ok(Self { addr, len })
+    }
+}
+
+impl Prefix {
+    pub fn new(addr: Ipv6Addr, len: u8) -> Self {
+        Self { addr, len }
+    }
+
+    pub fn contains(&self, addr: &Ipv6Addr) -> bool {
+        let mask = u128::MAX.checked_shl(128 - self.len as u32).unwrap_or(0);
+        u128::from(*addr) & mask == u128::from(self.addr) & mask
+    }
+}
+
+impl std::fmt::Display for Prefix {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}", self.addr, self.len)
+    }
+}
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct Target {
+    /// Minimal height of the wanted address
+    #[arg(long = "target-height", default_value_t = 0)]
+    pub height: u8,
+
+    /// Prefix the wanted address starts with, e.g. 200:1234::/32
+    #[arg(long = "target-prefix")]
+    pub prefix: Option<Prefix>,
+}
+
+impl Target {
+    pub fn is_set(&self) -> bool {
+        self.height > 0 || self.prefix.is_some()
+    }
+
+    /// Probability of a single random key producing a matching address.
+    pub fn probability(&self) -> f64 {
+        let (prefix, len) = match &self.prefix {
+            Some(p) => (u128::from(p.addr), p.len as u32),
+            None => (0, 0),
+        };
+        let header_mask = !(u16::MAX.checked_shr(len.min(16)).unwrap_or(0));
+        let header_prefix = (prefix >> 112) as u16 & header_mask;
+        // Address bits after the 0x02 and height bytes are taken from the
+        // public key right after its first set bit, so they are uniform.
+        let tail = 0.5f64.powi(len.saturating_sub(16) as i32);
+
+        (self.height..=u8::MAX)
+            .filter(|h| (0x0200 | *h as u16) & header_mask == header_prefix)
+            .map(|h| 0.5f64.powi(h as i32 + 1) * tail)
+            .sum()
+    }
+}
+
+impl std::fmt::Display for Target {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "height >= {}", self.height)?;
+        if let Some(prefix) = &self.prefix {
+            write!(f, ", prefix {}", prefix)?;
+        }
+        Ok(())
+    }
+}
+
+/// Expected time and time quantiles (in seconds) to find a key matching
+/// with probability `probability` at `hashrate` keys per second.
+pub fn times(probability: f64, hashrate: f64) -> (f64, Vec<(f64, f64)>) {
+    let rate = probability * hashrate;
+    let quantiles = QUANTILES
+        .iter()
+        .map(|q| (*q, -(1.0 - q).ln() / rate))
+        .collect();
+    (1.0 / rate, quantiles)
+}
+
+/// One-line summary of `times` for stats output.
+pub fn format_eta(probability: f64, hashrate: f64) -> String {
+    let (expected, quantiles) = times(probability, hashrate);
+    let quantiles: Vec<_> = quantiles
+        .iter()
+        .map(|(q, t)| format!("{:.0}%: {}", q * 100.0, format_duration(*t)))
+        .collect();
+    format!("{} ({})", format_duration(expected), quantiles.join(", "))
+}
+
+pub fn print_estimate(target: &Target, hashrate: Option<f64>) {
+    let probability = target.probability();
+    println!("Target: {}", target);
+    println!("Probability per key: {:e}", probability);
+    println!("Expected keys: {:.3e}", 1.0 / probability);
+    if let Some(hashrate) = hashrate {
+        let (expected, quantiles) = times(probability, hashrate * 1_000_000.0);
+        println!(
+            "Expected time at {} MH/s: {}",
+            hashrate,
+            format_duration(expected)
+        );
+        for (q, t) in quantiles {
+            println!("{:.0}% chance within: {}", q * 100.0, format_duration(t));
+        }
+    }
+}
+
+pub fn format_duration(secs: f64) -> String {
+    if !secs.is_finite() {
+        return String::from("never");
+    }
+    let total = secs as u64;
+    let (days, hours, mins, whole_secs) = (
+        total / 86400,
+        total / 3600 % 24,
+        total / 60 % 60,
+        total % 60,
+    );
+    if days > 0 {
+        format!("{}d {:02}h", days, hours)
+    } else if hours > 0 {
+        format!("{}h {:02}m", hours, mins)
+    } else if mins > 0 {
+        format!("{}m {:02}s", mins, whole_secs)
+    } else {
+        format!("{:.2}s", secs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use super::{Prefix, Target};
+
+    fn target(height: u8, prefix: Option<&str>) -> Target {
+        Target {
+            height,
+            prefix: prefix.map(|p| Prefix::from_str(p).unwrap()),
+        }
+    }
+
+    fn assert_close(a: f64, b: f64) {
+        assert!((a - b).abs() <= b * 1e-12, "{a} != {b}");
+    }
+
+    #[test]
+    fn test_probability() {
+        assert_close(target(0, None).probability(), 1.0);
+        assert_close(target(14, None).probability(), 0.5f64.powi(14));
+        // Height 14 exactly, then 16 more fixed bits
+        assert_close(
+            target(0, Some("20e:1234::/32")).probability(),
+            0.5f64.powi(15 + 16),
+        );
+        assert_eq!(target(15, Some("20e:1234::/32")).probability(), 0.0);
+        assert_eq!(target(0, Some("300::/8")).probability(), 0.0);
+        // 0x0200..=0x020f: heights 0 to 15
+        assert_close(
+            target(14, Some("200::/12")).probability(),
+            0.5f64.powi(15) + 0.5f64.powi(16),
+        );
+    }
+}
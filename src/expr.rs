@@ -0,0 +1,309 @@
+//! Boolean pattern expressions such as
+//! `prefix(200::/12) && "cafe" && !"cafe$" && height >= 10`.
+//!
+//! - `"regex"` matches the pattern's match target, `expanded("regex")` and
+//!   the like match another one
+//! - `prefix(addr/len)` checks leading address bits
+//! - `height` compares with `<`, `<=`, `>`, `>=`, `==` or `!=`
+//! - `!`, `&&`, `||` and parentheses combine them
+
+use std::str::FromStr;
+
+use regex::Regex;
+
+use crate::{
+    estimate::Prefix,
+    patterns::{self, Candidate, MatchTarget},
+};
+
+#[derive(Debug, Clone, Copy)]
+pub enum Cmp {
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Eq,
+    Ne,
+}
+
+pub enum Expr {
+    Regex(Regex, MatchTarget),
+    Prefix(Prefix),
+    Height(Cmp, u8),
+    Not(Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+}
+
+impl Expr {
+    /// Parses `s`, matching bare regexes against `target`.
+    pub fn parse(s: &str, target: MatchTarget) -> Result<Self, String> {
+        let mut parser = Parser { s, pos: 0, target };
+        let expr = parser.or()?;
+        parser.skip_whitespace();
+        if parser.pos != s.len() {
+            return Err(parser.error("unexpected input"));
+        }
+        Ok(expr)
+    }
+
+    pub fn is_match(&self, candidate: &mut Candidate) -> bool {
+        match self {
+            Self::Regex(re, target) => re.is_match(candidate.text(*target)),
+            Self::Prefix(prefix) => prefix.contains(candidate.addr),
+            Self::Height(cmp, value) => {
+                let height = candidate.height;
+                match cmp {
+                    Cmp::Lt => height < *value,
+                    Cmp::Le => height <= *value,
+                    Cmp::Gt => height > *value,
+                    Cmp::Ge => height >= *value,
+                    Cmp::Eq => height == *value,
+                    Cmp::Ne => height != *value,
+                }
+            }
+            Self::Not(expr) => !expr.is_match(candidate),
+            Self::And(a, b) => a.is_match(candidate) && b.is_match(candidate),
+            Self::Or(a, b) => a.is_match(candidate) || b.is_match(candidate),
+        }
+    }
+
+    /// Prefixes at least one of which every matching address starts with.
+    pub fn prefixes(&self) -> Option<Vec<Prefix>> {
+        match self {
+            Self::Regex(re, target) => patterns::literal_prefixes(re.as_str(), *target),
+            Self::Prefix(prefix) => Some(vec![prefix.clone()]),
+            Self::Height(..) | Self::Not(_) => None,
+            Self::And(a, b) => a.prefixes().or_else(|| b.prefixes()),
+            Self::Or(a, b) => {
+                let mut prefixes = a.prefixes()?;
+                prefixes.extend(b.prefixes()?);
+                Some(prefixes)
+            }
+        }
+    }
+}
+
+struct Parser<'a> {
+    s: &'a str,
+    pos: usize,
+    target: MatchTarget,
+}
+
+impl<'a> Parser<'a> {
+    fn or(&mut self) -> Result<Expr, String> {
+        let mut expr = self.and()?;
+        while self.eat("||") {
+            expr = Expr::Or(Box::new(expr), Box::new(self.and()?));
+        }
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> Result<Expr, String> {
+        let mut expr = self.unary()?;
+        while self.eat("&&") {
+            expr = Expr::And(Box::new(expr), Box::new(self.unary()?));
+        }
+        Ok(expr)
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        if self.eat("!") {
+            return Ok(Expr::Not(Box::new(self.unary()?)));
+        }
+        if self.eat("(") {
+            let expr = self.or()?;
+            self.expect(")")?;
+            return Ok(expr);
+        }
+        self.skip_whitespace();
+        if self.rest().starts_with('"') {
+            return self.regex(self.target);
+        }
+
+        let start = self.pos;
+        let name = self.ident();
+        match name {
+            "height" => {
+                let cmp = self.cmp()?;
+                self.skip_whitespace();
+                let digits = self.take_while(|c| c.is_ascii_digit());
+                let value = u8::from_str(digits).map_err(|e| self.error(&e.to_string()))?;
+                Ok(Expr::Height(cmp, value))
+            }
+            "prefix" => {
+                self.expect("(")?;
+                let prefix = self.take_while(|c| c != ')').trim();
+                let prefix = Prefix::from_str(prefix).map_err(|e| self.error(&e))?;
+                self.expect(")")?;
+                Ok(Expr::Prefix(prefix))
+            }
+            "" => Err(self.error("expected an expression")),
+            _ => {
+                let target = MatchTarget::from_str(name).map_err(|_| {
+                    self.pos = start;
+                    self.error(&format!("unknown function {:?}", name))
+                })?;
+                self.expect("(")?;
+                let expr = self.regex(target)?;
+                self.expect(")")?;
+                Ok(expr)
+            }
+        }
+    }
+
+    fn cmp(&mut self) -> Result<Cmp, String> {
+        for (op, cmp) in [
+            ("<=", Cmp::Le),
+            (">=", Cmp::Ge),
+            ("==", Cmp::Eq),
+            ("!=", Cmp::Ne),
+            ("<", Cmp::Lt),
+            (">", Cmp::Gt),
+        ] {
+            if self.eat(op) {
+                return Ok(cmp);
+            }
+        }
+        Err(self.error("expected a comparison"))
+    }
+
+    /// A regex in double quotes, where `\"` stands for a quote.
+    fn regex(&mut self, target: MatchTarget) -> Result<Expr, String> {
+        self.expect("\"")?;
+        let mut regex = String::new();
+        let mut chars = self.rest().char_indices();
+        loop {
+            match chars.next() {
+                Some((i, '"')) => {
+                    self.pos += i + 1;
+                    break;
+                }
+                Some((_, '\\')) if chars.clone().next().map(|(_, c)| c) == Some('"') => {
+                    chars.next();
+                    regex.push('"');
+                }
+                Some((_, c)) => regex.push(c),
+                None => return Err(self.error("unterminated regex")),
+            }
+        }
+        let regex = Regex::new(&regex).map_err(|e| self.error(&e.to_string()))?;
+        Ok(Expr::Regex(regex, target))
+    }
+
+    fn ident(&mut self) -> &'a str {
+        self.skip_whitespace();
+        self.take_while(|c| c.is_ascii_alphanumeric() || c == '-')
+    }
+
+    fn take_while(&mut self, f: impl Fn(char) -> bool) -> &'a str {
+        let start = self.pos;
+        let len = self
+            .rest()
+            .find(|c| !f(c))
+            .unwrap_or_else(|| self.rest().len());
+        self.pos += len;
+        &self.s[start..self.pos]
+    }
+
+    fn eat(&mut self, token: &str) -> bool {
+        self.skip_whitespace();
+        let found = self.rest().starts_with(token);
+        if found {
+            self.pos += token.len();
+        }
+        found
+    }
+
+    fn expect(&mut self, token: &str) -> Result<(), String> {
+        if self.eat(token) {
+            Ok(())
+        } else {
+            Err(self.error(&format!("expected {:?}", token)))
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        self.take_while(char::is_whitespace);
+    }
+
+    fn rest(&self) -> &'a str {
+        &self.s[self.pos..]
+    }
+
+    fn error(&self, message: &str) -> String {
+        format!(
+            "column {}: {}",
+            self.s[..self.pos].chars().count() + 1,
+            message
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::Ipv6Addr, str::FromStr};
+
+    use super::Expr;
+    use crate::patterns::{Candidate, MatchTarget};
+
+    fn is_match(expr: &str, addr: &str, height: u8) -> bool {
+        let addr = Ipv6Addr::from_str(addr).unwrap();
+        let mut candidate = Candidate::new(&addr, &[0; 32], height);
+        Expr::parse(expr, MatchTarget::Compressed)
+            .unwrap()
+            .is_match(&mut candidate)
+    }
+
+    #[test]
+    fn test_eval() {
+        let expr = r#""cafe" && !"cafe$""#;
+        assert!(is_match(expr, "200:cafe::1", 0));
+        assert!(!is_match(expr, "200::cafe", 0));
+
+        let expr = "prefix(200::/12) && !(height < 10)";
+        assert!(is_match(expr, "20a::1", 10));
+        assert!(!is_match(expr, "20a::1", 9));
+        assert!(!is_match(expr, "210::1", 10));
+
+        let expr = r#"expanded("^0200:0000:") || raw("1$")"#;
+        assert!(is_match(expr, "200::1", 0));
+        assert!(is_match(expr, "200:1::1", 0));
+        assert!(!is_match(expr, "200:1::2", 0));
+
+        assert!(is_match(r#""\"" || height == 3"#, "200::", 3));
+    }
+
+    #[test]
+    fn test_errors() {
+        let err = |s| match Expr::parse(s, MatchTarget::Compressed) {
+            Ok(_) => panic!("{} parsed", s),
+            Err(e) => e,
+        };
+        assert_eq!(err("\"a\" &&"), "column 7: expected an expression");
+        assert_eq!(err("height = 3"), "column 8: expected a comparison");
+        assert_eq!(err("foo(\"a\")"), "column 1: unknown function \"foo\"");
+        assert!(err("\"(\"").starts_with("column 4: regex parse error"));
+        assert_eq!(err("(\"a\""), "column 5: expected \")\"");
+    }
+
+    #[test]
+    fn test_prefixes() {
+        let prefixes = |s| {
+            Expr::parse(s, MatchTarget::Compressed)
+                .unwrap()
+                .prefixes()
+                .map(|p| p.iter().map(|p| p.to_string()).collect::<Vec<_>>())
+        };
+        assert_eq!(
+            prefixes(r#"height > 3 && "^200:1:""#),
+            Some(vec![String::from("200:1::/32")])
+        );
+        assert_eq!(
+            prefixes(r#""^200:1:" || prefix(300::/8)"#),
+            Some(vec![String::from("200:1::/32"), String::from("300::/8")])
+        );
+        assert_eq!(prefixes(r#""^200:1:" || "cafe""#), None);
+        assert_eq!(prefixes(r#"!"^200:1:""#), None);
+    }
+}
@@ -1,156 +1,226 @@
-use std::sync::atomic::AtomicU8;
+use std::sync::{mpsc, Arc, Mutex};
 
-use rand::RngCore;
-use rayon::prelude::*;
-use regex::Regex;
 use wgpu::{BindGroup, Buffer, ComputePipeline, Device, Queue};
 
-use crate::{PublicKey, Seed};
+use crate::{backend::KeyBackend, error::Error, PublicKey, Seed};
 
-pub fn start_gpu(batch_size: usize, stats: bool, regexes: Vec<Regex>) {
-    let shader_binary = wgpu::include_spirv_raw!(env!("kernel.spv"));
-
-    futures::executor::block_on(start_internal(
-        shader_binary,
-        batch_size * 64,
-        stats,
-        regexes,
-    ));
+/// Derives keys with the Vulkan compute shader.
+pub struct GpuBackend {
+    name: String,
+    batch_size: usize,
+    device: Device,
+    queue: Queue,
+    bind_group: BindGroup,
+    compute_pipeline: ComputePipeline,
+    storage_buffer: Buffer,
+    readback_buffer: Buffer,
+    /// Last error wgpu reported outside of an error scope.
+    error: Arc<Mutex<Option<String>>>,
 }
 
-async fn start_internal(
-    shader_binary: wgpu::ShaderModuleDescriptorSpirV<'static>,
-    batch_size: usize,
-    print_stats: bool,
-    regexes: Vec<Regex>,
-) {
-    let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
-        backends: wgpu::Backends::VULKAN,
-        ..Default::default()
-    });
+impl GpuBackend {
+    /// Sets up the GPU number `device_index`, or the most powerful one, for
+    /// batches of `batch_size` keys.
+    pub fn new(batch_size: usize, device_index: Option<usize>) -> Result<Self, Error> {
+        let shader_binary = wgpu::include_spirv_raw!(env!("kernel.spv"));
 
-    let adapter_options = wgpu::RequestAdapterOptions {
-        power_preference: wgpu::PowerPreference::HighPerformance,
-        ..Default::default()
-    };
-
-    let adapter = instance
-        .request_adapter(&adapter_options)
-        .await
-        .expect("Failed to find an appropriate adapter");
-
-    let mut limits = wgpu::Limits::default();
-    limits.max_storage_buffer_binding_size = (batch_size * 32) as u32;
-
-    let (device, queue) = adapter
-        .request_device(
-            &wgpu::DeviceDescriptor {
-                label: None,
-                features: wgpu::Features::SPIRV_SHADER_PASSTHROUGH,
-                limits: limits,
-            },
-            None,
-        )
-        .await
-        .expect("Failed to create device");
-
-    drop(instance);
-    drop(adapter);
-
-    let shader_module = unsafe { device.create_shader_module_spirv(&shader_binary) };
-
-    let readback_buffer = device.create_buffer(&wgpu::BufferDescriptor {
-        label: None,
-        size: (batch_size * 32) as wgpu::BufferAddress,
-        usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
-        mapped_at_creation: false,
-    });
+        futures::executor::block_on(Self::new_internal(shader_binary, batch_size, device_index))
+    }
 
-    let storage_buffer = device.create_buffer(&wgpu::BufferDescriptor {
-        label: None,
-        size: (batch_size * 32) as wgpu::BufferAddress,
-        usage: wgpu::BufferUsages::STORAGE
-            | wgpu::BufferUsages::COPY_DST
-            | wgpu::BufferUsages::COPY_SRC,
-        mapped_at_creation: false,
-    });
+    async fn new_internal(
+        shader_binary: wgpu::ShaderModuleDescriptorSpirV<'static>,
+        batch_size: usize,
+        device_index: Option<usize>,
+    ) -> Result<Self, Error> {
+        let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
+            backends: wgpu::Backends::VULKAN,
+            ..Default::default()
+        });
 
-    let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-        label: None,
-        entries: &[wgpu::BindGroupLayoutEntry {
-            binding: 0,
-            count: None,
-            visibility: wgpu::ShaderStages::COMPUTE,
-            ty: wgpu::BindingType::Buffer {
-                has_dynamic_offset: false,
-                min_binding_size: None,
-                ty: wgpu::BufferBindingType::Storage { read_only: false },
-            },
-        }],
-    });
+        let adapter_options = wgpu::RequestAdapterOptions {
+            power_preference: wgpu::PowerPreference::HighPerformance,
+            ..Default::default()
+        };
 
-    let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-        label: None,
-        layout: &bind_group_layout,
-        entries: &[wgpu::BindGroupEntry {
-            binding: 0,
-            resource: storage_buffer.as_entire_binding(),
-        }],
-    });
+        let adapter = match device_index {
+            Some(index) => instance
+                .enumerate_adapters(wgpu::Backends::VULKAN)
+                .nth(index)
+                .ok_or_else(|| Error::Device(format!("no GPU number {}", index)))?,
+            None => instance
+                .request_adapter(&adapter_options)
+                .await
+                .ok_or_else(|| Error::Device(String::from("no Vulkan adapter found")))?,
+        };
+        let name = adapter.get_info().name;
 
-    let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-        label: None,
-        bind_group_layouts: &[&bind_group_layout],
-        push_constant_ranges: &[],
-    });
+        let supported = adapter.limits();
+        let buffer_size = batch_size * 32;
+        if buffer_size > supported.max_storage_buffer_binding_size as usize
+            || buffer_size as u64 > supported.max_buffer_size
+        {
+            return Err(Error::Limits(format!(
+                "{} keys need {} byte buffers, {} allows at most {}",
+                batch_size, buffer_size, name, supported.max_storage_buffer_binding_size
+            )));
+        }
+        let workgroups = batch_size / crate::WORKGROUP_SIZE;
+        if workgroups > supported.max_compute_workgroups_per_dimension as usize {
+            return Err(Error::Limits(format!(
+                "{} keys need {} workgroups, {} allows at most {}",
+                batch_size, workgroups, name, supported.max_compute_workgroups_per_dimension
+            )));
+        }
 
-    let compute_pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
-        label: None,
-        layout: Some(&pipeline_layout),
-        module: &shader_module,
-        entry_point: "main",
-    });
+        let limits = wgpu::Limits {
+            max_storage_buffer_binding_size: buffer_size as u32,
+            ..Default::default()
+        };
 
-    let mut pubkeys: Vec<PublicKey> = vec![[0xFFu8; 32]; batch_size];
-    let mut new_seeds: Vec<Seed> = vec![[0u8; 32]; batch_size];
-    let mut current_seeds: Vec<Seed> = vec![[0u8; 32]; batch_size];
+        let (device, queue) = adapter
+            .request_device(
+                &wgpu::DeviceDescriptor {
+                    label: None,
+                    features: wgpu::Features::SPIRV_SHADER_PASSTHROUGH,
+                    limits,
+                },
+                None,
+            )
+            .await
+            .map_err(|e| Error::Device(format!("{}: {}", name, e)))?;
 
-    let max_leading_zeros: Vec<AtomicU8> = (0..(regexes.len())).map(|_| AtomicU8::new(0)).collect();
-    let mut first_run = true;
+        drop(instance);
+        drop(adapter);
 
-    rand::thread_rng().fill_bytes(&mut new_seeds.flatten_mut());
-    loop {
-        let start_now = std::time::Instant::now();
+        let error = Arc::new(Mutex::new(None));
+        device.on_uncaptured_error(Box::new({
+            let error = error.clone();
+            move |e| {
+                error.lock().unwrap().get_or_insert(e.to_string());
+            }
+        }));
 
-        start_compute_pass(
-            &device,
-            &bind_group,
-            &compute_pipeline,
-            batch_size,
-            &storage_buffer,
-            &readback_buffer,
-            &queue,
-            new_seeds.flatten(),
-        );
+        device.push_error_scope(wgpu::ErrorFilter::Validation);
+        let shader_module = unsafe { device.create_shader_module_spirv(&shader_binary) };
+
+        let readback_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: None,
+            size: (batch_size * 32) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let storage_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: None,
+            size: (batch_size * 32) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::STORAGE
+                | wgpu::BufferUsages::COPY_DST
+                | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
 
-        if !first_run {
-            handle_keypairs(&current_seeds, &pubkeys, &regexes, &max_leading_zeros);
-        } else {
-            first_run = false;
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: None,
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                count: None,
+                visibility: wgpu::ShaderStages::COMPUTE,
+                ty: wgpu::BindingType::Buffer {
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                    ty: wgpu::BufferBindingType::Storage { read_only: false },
+                },
+            }],
+        });
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: None,
+            layout: &bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: storage_buffer.as_entire_binding(),
+            }],
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: None,
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        let compute_pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: None,
+            layout: Some(&pipeline_layout),
+            module: &shader_module,
+            entry_point: "main",
+        });
+        if let Some(e) = device.pop_error_scope().await {
+            return Err(Error::Shader(e.to_string()));
         }
-        std::mem::swap(&mut current_seeds, &mut new_seeds);
-        rand::thread_rng().fill_bytes(&mut new_seeds.flatten_mut());
 
-        read_pubkeys(&device, &readback_buffer, pubkeys.flatten_mut());
+        Ok(Self {
+            name,
+            batch_size,
+            device,
+            queue,
+            bind_group,
+            compute_pipeline,
+            storage_buffer,
+            readback_buffer,
+            error,
+        })
+    }
 
-        if print_stats {
-            let time_elapsed = (std::time::Instant::now() - start_now).as_secs_f64();
-            let hashrate = batch_size as f64 / time_elapsed / 1_000_000.0;
-            println!("Hashrate: {:.2} MH/s", hashrate);
+    /// Fails with the last error wgpu reported, if any.
+    fn check(&self) -> Result<(), Error> {
+        match self.error.lock().unwrap().take() {
+            Some(e) => Err(Error::Gpu(e)),
+            None => Ok(()),
         }
     }
 }
 
+impl KeyBackend for GpuBackend {
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn batch_size(&self) -> usize {
+        self.batch_size
+    }
+
+    fn submit(&mut self, seeds: &[Seed]) -> Result<(), Error> {
+        start_compute_pass(
+            &self.device,
+            &self.bind_group,
+            &self.compute_pipeline,
+            self.batch_size,
+            &self.storage_buffer,
+            &self.readback_buffer,
+            &self.queue,
+            seeds.flatten(),
+        );
+        self.check()
+    }
+
+    fn collect(&mut self, pubkeys: &mut [PublicKey]) -> Result<(), Error> {
+        read_pubkeys(&self.device, &self.readback_buffer, pubkeys.flatten_mut())?;
+        self.check()
+    }
+
+    /// The storage buffer holds seeds of the pass that was never read.
+    fn clear(&mut self) -> Result<(), Error> {
+        clear_buffers(
+            &self.device,
+            &self.queue,
+            &self.storage_buffer,
+            &self.readback_buffer,
+        );
+        self.check()
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
 fn start_compute_pass(
     device: &Device,
     bind_group: &BindGroup,
@@ -165,41 +235,56 @@ fn start_compute_pass(
         device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
     {
         let mut cpass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor { label: None });
-        cpass.set_bind_group(0, &bind_group, &[]);
-        cpass.set_pipeline(&compute_pipeline);
+        cpass.set_bind_group(0, bind_group, &[]);
+        cpass.set_pipeline(compute_pipeline);
         cpass.dispatch_workgroups((batch_size / crate::WORKGROUP_SIZE) as u32, 1, 1);
     }
     encoder.copy_buffer_to_buffer(
-        &storage_buffer,
+        storage_buffer,
         0,
-        &readback_buffer,
+        readback_buffer,
         0,
         (batch_size * 32) as wgpu::BufferAddress,
     );
-    queue.write_buffer(&storage_buffer, 0, seeds);
+    queue.write_buffer(storage_buffer, 0, seeds);
     queue.submit(Some(encoder.finish()));
 }
 
-fn read_pubkeys(device: &Device, readback_buffer: &Buffer, pubkeys: &mut [u8]) {
+fn read_pubkeys(
+    device: &Device,
+    readback_buffer: &Buffer,
+    pubkeys: &mut [u8],
+) -> Result<(), Error> {
     let pubkeys_slice = readback_buffer.slice(..);
-    pubkeys_slice.map_async(wgpu::MapMode::Read, |r| r.unwrap());
+    let (sender, receiver) = mpsc::channel();
+    pubkeys_slice.map_async(wgpu::MapMode::Read, move |r| {
+        // The receiver is only gone if polling below panicked.
+        let _ = sender.send(r);
+    });
     device.poll(wgpu::Maintain::Wait);
+    match receiver.try_recv() {
+        Ok(Ok(())) => {}
+        Ok(Err(e)) => return Err(Error::Gpu(format!("reading back keys failed: {}", e))),
+        Err(_) => return Err(Error::Gpu(String::from("reading back keys didn't finish"))),
+    }
     let pubkeys_range = pubkeys_slice.get_mapped_range();
     pubkeys.copy_from_slice(&pubkeys_range);
     drop(pubkeys_range);
     readback_buffer.unmap();
+    Ok(())
 }
 
-fn handle_keypairs(
-    seeds: &Vec<Seed>,
-    pubkeys: &Vec<PublicKey>,
-    regexes: &Vec<Regex>,
-    max_leading_zeros: &Vec<AtomicU8>,
+/// Overwrites GPU buffers with zeroes so no seeds are left in device memory.
+fn clear_buffers(
+    device: &Device,
+    queue: &Queue,
+    storage_buffer: &Buffer,
+    readback_buffer: &Buffer,
 ) {
-    pubkeys
-        .par_iter()
-        .zip(seeds.par_iter())
-        .for_each(|(pk, seed)| {
-            crate::handler::handle_keypair(seed, pk, regexes, max_leading_zeros)
-        });
+    let mut encoder =
+        device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+    encoder.clear_buffer(storage_buffer, 0, None);
+    encoder.clear_buffer(readback_buffer, 0, None);
+    queue.submit(Some(encoder.finish()));
+    device.poll(wgpu::Maintain::Wait);
 }
@@ -1,45 +1,70 @@
-use crate::{PublicKey, Seed};
+use crate::{
+    miner::{Context, Hit},
+    patterns::{Candidate, PatternSet},
+    PublicKey, Seed,
+};
 
 use hex::ToHex;
-use regex::Regex;
 
-use std::io::Write;
-use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::atomic::Ordering;
 
-pub fn handle_keypair(
-    seed: &Seed,
-    pk: &PublicKey,
-    regexes: &Vec<Regex>,
-    max_leading_zeros: &Vec<AtomicU8>,
-) {
+pub fn handle_keypair(seed: &Seed, pk: &PublicKey, patterns: &PatternSet, context: &Context) {
+    let Context { stats, sink, .. } = context;
     let leading_zeros = leading_zeros_of_pubkey(pk);
 
-    let str_addr = address_for_pubkey(pk).to_string();
+    if patterns.scores_by_height() && (leading_zeros as u32) < stats.lowest_threshold() {
+        return;
+    }
+    let addr = address_for_pubkey(pk);
+    if !patterns.may_match(&addr, leading_zeros) {
+        return;
+    }
+    let mut candidate = Candidate::new(&addr, pk, leading_zeros);
+    let matches = patterns.matches(&mut candidate);
+    if matches.is_empty() {
+        return;
+    }
 
-    for (re, mlz) in regexes.iter().zip(max_leading_zeros.iter()) {
-        if mlz.load(Ordering::Relaxed) > leading_zeros {
+    for i in matches {
+        let pattern = &patterns.patterns[i];
+        if leading_zeros < pattern.min_height {
             continue;
         }
-        if !(re.is_match(&str_addr)) {
+        let score = patterns.score(i, &mut candidate);
+        if !stats.may_report(i, score) {
             continue;
         }
 
-        if mlz.fetch_max(leading_zeros, Ordering::AcqRel) <= leading_zeros {
-            let mut sk = [0u8; 64];
-            sk[..32].copy_from_slice(seed);
-            sk[32..].copy_from_slice(pk);
-            let mut lock = std::io::stdout().lock();
-            writeln!(lock, "=======================================").unwrap();
-            writeln!(lock, "PrivateKey: {}", sk.encode_hex::<String>()).unwrap();
-            writeln!(lock, "PublicKey: {}", pk.encode_hex::<String>()).unwrap();
-            writeln!(lock, "Address: {}", str_addr).unwrap();
-            writeln!(lock, "Height: {}", leading_zeros).unwrap();
-            writeln!(lock, "=======================================").unwrap();
+        if crate::cpu::dalek_public_from_seed(seed) != *pk {
+            stats.verification_failures.fetch_add(1, Ordering::Relaxed);
+            log::error!(
+                "Public key {} does not match its seed, skipping",
+                pk.encode_hex::<String>()
+            );
+            return;
+        }
+
+        if let Some(rank) = stats.report(i, score, leading_zeros) {
+            let hit = Hit {
+                seed,
+                public_key: pk,
+                address: addr,
+                height: leading_zeros,
+                pattern,
+                score,
+                rank,
+            };
+            let Some(sink) = sink else {
+                continue;
+            };
+            if let Err(e) = sink.found(&hit) {
+                context.fail(e);
+            }
         };
     }
 }
 
-fn leading_zeros_of_pubkey(pk: &[u8]) -> u8 {
+pub fn leading_zeros_of_pubkey(pk: &[u8]) -> u8 {
     let mut zeros = 0u8;
     for b in pk {
         let z = b.leading_zeros();
@@ -51,7 +76,7 @@ fn leading_zeros_of_pubkey(pk: &[u8]) -> u8 {
     zeros
 }
 
-fn address_for_pubkey(pk: &[u8]) -> std::net::Ipv6Addr {
+pub fn address_for_pubkey(pk: &[u8]) -> std::net::Ipv6Addr {
     let zeros = leading_zeros_of_pubkey(pk);
     let mut buf = [0u8; 16];
     buf[0] = 0x02;
@@ -0,0 +1,54 @@
+//! Vulkan-based miner for Yggdrasil addresses.
+//!
+//! ```no_run
+//! use ygglkan::{patterns, Backend, Error, Hit, Miner};
+//!
+//! let patterns = patterns::from_args(&[String::from("^200:cafe")], Default::default()).unwrap();
+//! let miner = Miner::builder()
+//!     .backend(Backend::Cpu)
+//!     .patterns(patterns)
+//!     .stop_after_results(1)
+//!     .sink(|hit: &Hit| -> Result<(), Error> {
+//!         println!("{}", hit.address);
+//!         Ok(())
+//!     })
+//!     .build()
+//!     .unwrap();
+//! miner.run().unwrap();
+//! ```
+
+#![feature(slice_flatten)]
+#![cfg_attr(test, feature(test))]
+
+const WORKGROUP_SIZE: usize = 64;
+
+mod backend;
+#[cfg(unix)]
+pub mod control;
+mod cpu;
+pub mod crypto;
+pub mod distributed;
+mod error;
+pub mod estimate;
+pub mod export;
+pub mod expr;
+mod gpu;
+mod handler;
+pub mod metrics;
+pub mod miner;
+pub mod output;
+pub mod patterns;
+pub mod score;
+pub mod seeds;
+pub mod stats;
+pub mod stream;
+pub mod units;
+
+pub use backend::Backend;
+pub use error::Error;
+pub use handler::{address_for_pubkey, leading_zeros_of_pubkey};
+pub use miner::{Hit, Miner, MinerBuilder, ResultSink};
+pub use stream::{Event, Found, MinerStream, Progress};
+
+pub type PublicKey = [u8; 32];
+pub type Seed = [u8; 32];
@@ -1,56 +1,277 @@
-#![feature(slice_flatten)]
+use std::{
+    fs::OpenOptions,
+    net::{SocketAddr, TcpListener},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
 
-use clap::Parser;
-use regex::Regex;
-use strum_macros::{Display, EnumString};
+use clap::{CommandFactory, FromArgMatches, Parser, Subcommand};
+use ygglkan::{
+    crypto,
+    distributed::{Coordinator, Worker},
+    estimate, export, metrics, output, patterns, score, stats, Backend, Error, Miner,
+};
 
-const WORKGROUP_SIZE: usize = 64;
-
-mod cpu;
-mod gpu;
-mod handler;
-
-type PublicKey = [u8; 32];
-type Seed = [u8; 32];
-
-#[derive(Debug, Clone, EnumString, Display)]
-enum Backend {
-    CPU,
-    GPU,
-}
+mod config;
+mod tui;
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 struct Args {
     /// GPU block size. Each block has 64 keys
-    #[arg(short, long, default_value_t = 1024)]
+    #[arg(short, long, env = "YGGLKAN_BATCH_SIZE", default_value_t = 1024)]
     batch_size: usize,
 
     /// Print hashrate stats
-    #[arg(short, long, default_value_t = false)]
+    #[arg(short, long, env = "YGGLKAN_STATS", default_value_t = false)]
     stats: bool,
 
+    /// Seconds between hashrate stats reports
+    #[arg(
+        long,
+        env = "YGGLKAN_STATS_INTERVAL",
+        default_value_t = 5,
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    stats_interval: u64,
+
     /// Regex pattern to search
     #[arg(short, long)]
     regexes: Option<Vec<String>>,
 
-    /// Which backend to use (CPU/GPU)
-    #[arg(long, default_value_t = Backend::GPU)]
+    /// Read patterns from this file: a regex per line, or TOML if it ends with .toml
+    #[arg(long, env = "YGGLKAN_PATTERNS", conflicts_with = "regexes")]
+    patterns: Option<PathBuf>,
+
+    /// Skip addresses matching this regex, for all patterns
+    #[arg(long)]
+    exclude: Vec<String>,
+
+    /// What regexes are matched against (compressed/expanded/raw/public-key)
+    #[arg(long, env = "YGGLKAN_MATCH_TARGET", default_value_t = patterns::MatchTarget::Compressed)]
+    match_target: patterns::MatchTarget,
+
+    /// How results are ranked (height/zero-run/repeats/zero-groups/words)
+    #[arg(long, env = "YGGLKAN_SCORE", default_value_t = score::Score::Height)]
+    score: score::Score,
+
+    /// Which matches to report: all, best (new best score per pattern),
+    /// at-least:N (height of at least N) or top:K (best K per pattern)
+    #[arg(long, env = "YGGLKAN_REPORT", default_value_t = stats::Report::Best)]
+    report: stats::Report,
+
+    /// Words counted by the words score, one per line
+    #[arg(long, env = "YGGLKAN_WORDS")]
+    words: Option<PathBuf>,
+
+    /// Which backend to use (CPU/CPU-KERNEL/GPU)
+    #[arg(long, env = "YGGLKAN_BACKEND", default_value_t = Backend::Gpu)]
     backend: Backend,
+
+    /// Index of the GPU to use, instead of the most powerful one
+    #[arg(long, env = "YGGLKAN_DEVICE")]
+    device: Option<usize>,
+
+    /// Stop after this many seconds
+    #[arg(long, env = "YGGLKAN_MAX_TIME")]
+    max_time: Option<u64>,
+
+    /// Stop after finding this many results
+    #[arg(long, env = "YGGLKAN_MAX_RESULTS")]
+    max_results: Option<u64>,
+
+    /// Append found keys to this file instead of printing them
+    #[arg(short, long, env = "YGGLKAN_OUTPUT", conflicts_with = "output_dir")]
+    output: Option<PathBuf>,
+
+    /// Append found keys to a file per pattern in this directory
+    #[arg(long, env = "YGGLKAN_OUTPUT_DIR")]
+    output_dir: Option<PathBuf>,
+
+    /// Encrypt saved keys with a passphrase (taken from YGGLKAN_PASSPHRASE or prompted)
+    #[arg(long, env = "YGGLKAN_ENCRYPT")]
+    encrypt: bool,
+
+    /// Also export found keys as unencrypted OpenSSH and PKCS#8 files to this directory
+    #[arg(long, env = "YGGLKAN_EXPORT_DIR", conflicts_with = "encrypt")]
+    export_dir: Option<PathBuf>,
+
+    /// Show a dashboard with hashrate graphs and results instead of
+    /// printing them, needs an output file or directory
+    #[arg(long, env = "YGGLKAN_TUI")]
+    tui: bool,
+
+    /// Serve Prometheus metrics on this address
+    #[arg(long, env = "YGGLKAN_METRICS_LISTEN")]
+    metrics_listen: Option<SocketAddr>,
+
+    /// Take JSON commands to change patterns and pause mining on a Unix
+    /// socket at this path
+    #[arg(long, env = "YGGLKAN_CONTROL_SOCKET")]
+    control_socket: Option<PathBuf>,
+
+    /// Read settings not given as options or YGGLKAN_* variables from this TOML file
+    #[arg(long, env = "YGGLKAN_CONFIG")]
+    config: Option<PathBuf>,
+
+    /// Target to show an ETA for in hashrate stats
+    #[command(flatten)]
+    target: estimate::Target,
+
+    #[command(subcommand)]
+    command: Option<Command>,
 }
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Estimate time needed to find an address
+    Estimate {
+        #[command(flatten)]
+        target: estimate::Target,
+
+        /// Hashrate in MH/s to estimate time for
+        #[arg(long)]
+        hashrate: Option<f64>,
+    },
+    /// List keys stored in a results file, decrypting them if needed
+    Show {
+        file: PathBuf,
+
+        /// Also print private keys
+        #[arg(long)]
+        reveal: bool,
+    },
+    /// Export keys of a results file as OpenSSH and PKCS#8 files, or show the
+    /// address of an OpenSSH or PEM key
+    Convert {
+        file: PathBuf,
+
+        /// Directory to export keys of a results file to
+        #[arg(long)]
+        export_dir: Option<PathBuf>,
+    },
+    /// Inspect the configuration
+    #[command(subcommand)]
+    Config(ConfigCommand),
+    /// Hand out work to workers and check and save what they find, using the
+    /// pattern, output and stop options
+    Coordinator {
+        /// Address to listen for workers on
+        #[arg(long)]
+        listen: SocketAddr,
+
+        /// Token workers authenticate with
+        #[arg(long, env = "YGGLKAN_TOKEN", hide_env_values = true)]
+        token: String,
+
+        /// Seeds in a work unit handed out to a worker
+        #[arg(
+            long,
+            default_value_t = 1u64 << 32,
+            value_parser = clap::value_parser!(u64).range(1..)
+        )]
+        unit_size: u64,
+
+        /// Slices of every finished unit to recompute on the CPU, 0 to trust
+        /// workers
+        #[arg(long, default_value_t = 1)]
+        spot_checks: usize,
+
+        /// Append a completion record of every finished unit to this file
+        #[arg(long)]
+        records: Option<PathBuf>,
+    },
+    /// Mine ranges handed out by a coordinator, sending results to it
+    Worker {
+        /// Address of the coordinator
+        #[arg(long)]
+        connect: String,
+
+        /// Token to authenticate with
+        #[arg(long, env = "YGGLKAN_TOKEN", hide_env_values = true)]
+        token: String,
+
+        /// Name shown in the coordinator's stats
+        #[arg(long, default_value = "worker")]
+        name: String,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum ConfigCommand {
+    /// Print the settings in effect after reading options, environment
+    /// variables and the config file, in config file format
+    Dump,
+}
+
 fn main() {
     env_logger::init();
 
-    let args = Args::parse();
+    if let Err(e) = parse_args().and_then(run) {
+        eprintln!("Error: {}", e);
+        std::process::exit(e.exit_code());
+    }
+}
+
+/// Parses options, filling in the rest from the `--config` file.
+fn parse_args() -> Result<Args, Error> {
+    let matches = Args::command().get_matches();
+    let mut args = Args::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    if let Some(path) = &args.config {
+        config::Config::load(path)?.apply(&mut args, &matches);
+    }
+    config::check(&args)?;
+    Ok(args)
+}
+
+#[cfg(unix)]
+fn control_socket(
+    miner: Arc<Miner>,
+    path: &Path,
+    defaults: patterns::Defaults,
+) -> Result<(), Error> {
+    ygglkan::control::spawn_server(miner, path, defaults)
+        .map_err(|e| Error::Io(format!("control socket {}", path.display()), e))
+}
+
+#[cfg(not(unix))]
+fn control_socket(_: Arc<Miner>, _: &Path, _: patterns::Defaults) -> Result<(), Error> {
+    Err(Error::Config(String::from(
+        "control sockets need a Unix system",
+    )))
+}
+
+fn run(args: Args) -> Result<(), Error> {
+    if let Some(Command::Config(ConfigCommand::Dump)) = args.command {
+        let config = toml::to_string(&config::Config::effective(&args))
+            .map_err(|e| Error::Config(e.to_string()))?;
+        print!("{}", config);
+        return Ok(());
+    }
+    match args.command {
+        Some(Command::Estimate { target, hashrate }) => {
+            estimate::print_estimate(&target, hashrate);
+            return Ok(());
+        }
+        Some(Command::Show { file, reveal }) => return output::show(&file, reveal),
+        Some(Command::Convert { file, export_dir }) => {
+            return export::convert(&file, export_dir.as_deref())
+        }
+        Some(Command::Config(_) | Command::Coordinator { .. } | Command::Worker { .. }) | None => {}
+    }
 
-    let regexes = match args.regexes {
-        Some(r) => r,
-        None => vec![String::from("")],
+    let defaults = patterns::Defaults {
+        target: args.match_target,
+        score: args.score,
+    };
+    let patterns = match (&args.patterns, args.regexes) {
+        (Some(file), _) => patterns::from_file(file, defaults)?,
+        (None, Some(r)) => patterns::from_args(&r, defaults)?,
+        (None, None) => patterns::from_args(&[String::from("")], defaults)?,
     };
-    let regexes: Vec<_> = regexes
-        .into_iter()
-        .map(|r| Regex::new(&r).unwrap())
-        .collect();
+    let excludes = patterns::from_args(&args.exclude, defaults)?;
+    let words = score::load_words(args.words.as_deref())?;
 
     println!("Starting miner...");
     println!(
@@ -60,8 +281,143 @@ fn main() {
     );
     println!("This may take a while due to shader compilation.");
 
-    match args.backend {
-        Backend::GPU => gpu::start_gpu(args.batch_size, args.stats, regexes),
-        Backend::CPU => cpu::start_cpu(args.stats, regexes),
+    let mut builder = Miner::builder()
+        .backend(args.backend)
+        .batch_size(args.batch_size)
+        .patterns(patterns)
+        .excludes(excludes)
+        .words(words)
+        .report(args.report);
+    let results = Arc::new(tui::Results::default());
+    let worker = match &args.command {
+        Some(Command::Worker {
+            connect,
+            token,
+            name,
+        }) => {
+            let worker = Worker::connect(connect, token, name)?;
+            println!("Connected to coordinator {}", connect);
+            builder = match args.tui {
+                true => builder.sink(tui::sink(results.clone(), worker.sink(false))),
+                false => builder.sink(worker.sink(true)),
+            };
+            builder = builder.seeds(worker.seeds());
+            Some(worker)
+        }
+        _ => {
+            let cipher = if args.encrypt {
+                Some(crypto::Cipher::new(&crypto::read_passphrase(true)?))
+            } else {
+                None
+            };
+            let output =
+                output::Output::new(args.output, args.output_dir, cipher, args.export_dir)?;
+            builder = match args.tui {
+                true => builder.sink(tui::sink(results.clone(), output.quiet())),
+                false => builder.sink(output),
+            };
+            None
+        }
+    };
+    if let Some(device) = args.device {
+        builder = builder.device(device);
+    }
+    if let Some(secs) = args.max_time {
+        builder = builder.stop_after(Duration::from_secs(secs));
+    }
+    if let Some(results) = args.max_results {
+        builder = builder.stop_after_results(results);
+    }
+    let miner = Arc::new(builder.build()?);
+
+    let stats = miner.stats();
+    // The reporter keeps the moving-average hashrate others read up to date.
+    let distributed = matches!(
+        args.command,
+        Some(Command::Coordinator { .. } | Command::Worker { .. })
+    );
+    if args.stats
+        || args.metrics_listen.is_some()
+        || args.tui
+        || args.control_socket.is_some()
+        || distributed
+    {
+        stats::spawn_reporter(
+            stats.clone(),
+            Duration::from_secs(args.stats_interval),
+            args.target.clone(),
+            args.stats && !args.tui,
+        );
+    }
+    if let Some(worker) = &worker {
+        worker.spawn_reporter(stats.clone(), Duration::from_secs(args.stats_interval));
+    }
+    if let Some(addr) = args.metrics_listen {
+        metrics::spawn_server(stats.clone(), addr)
+            .map_err(|e| Error::Io(format!("metrics listener on {}", addr), e))?;
+        println!("Serving metrics on http://{}/metrics", addr);
+    }
+    if let Some(path) = &args.control_socket {
+        control_socket(miner.clone(), path, defaults)?;
+        println!("Taking control commands on {}", path.display());
+    }
+
+    let handle = miner.clone();
+    ctrlc::set_handler(move || {
+        if handle.is_stopping() {
+            std::process::exit(130);
+        }
+        handle.stop();
+        eprintln!("Stopping, press Ctrl-C again to exit immediately...");
+    })
+    .map_err(|e| {
+        let e = std::io::Error::new(std::io::ErrorKind::Other, e);
+        Error::Io(String::from("Ctrl-C handler"), e)
+    })?;
+
+    let mine: Box<dyn FnOnce() -> Result<(), Error> + Send> = match args.command {
+        Some(Command::Coordinator {
+            listen,
+            token,
+            unit_size,
+            spot_checks,
+            records,
+        }) => {
+            let mut coordinator = Coordinator::new(miner.clone(), &token, unit_size)
+                .spot_checks(spot_checks)
+                .timeout(Duration::from_secs(args.stats_interval * 4));
+            if args.tui {
+                coordinator = coordinator.quiet();
+            }
+            if let Some(path) = records {
+                let file = OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(&path)
+                    .map_err(|e| Error::Io(path.display().to_string(), e))?;
+                coordinator = coordinator.records(file);
+            }
+            let listener = TcpListener::bind(listen)
+                .map_err(|e| Error::Io(format!("worker listener on {}", listen), e))?;
+            println!("Waiting for workers on {}", listen);
+            let coordinator = Arc::new(coordinator);
+            Box::new(move || {
+                coordinator.serve(listener);
+                Ok(())
+            })
+        }
+        _ => {
+            let miner = miner.clone();
+            Box::new(move || miner.run())
+        }
+    };
+    if !args.tui {
+        return mine();
     }
+
+    let mining = std::thread::spawn(mine);
+    tui::Dashboard::new(miner, results, defaults, &args.target).run(&mining)?;
+    mining
+        .join()
+        .unwrap_or_else(|e| std::panic::resume_unwind(e))
 }
@@ -0,0 +1,190 @@
+use std::{
+    fmt::Write as _,
+    io::{BufRead, BufReader, Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    sync::{atomic::Ordering, Arc},
+    time::Duration,
+};
+
+use crate::stats::Stats;
+
+/// Time a client gets for each read and write, as requests are served one
+/// at a time.
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Bytes of a request read at most, so a client can't make it grow until
+/// memory runs out.
+const MAX_REQUEST: u64 = 64 << 10;
+
+/// Starts serving `stats` in Prometheus text format on `addr`.
+pub fn spawn_server(stats: Arc<Stats>, addr: SocketAddr) -> std::io::Result<()> {
+    let listener = TcpListener::bind(addr)?;
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let result = stream.and_then(|s| serve(s, &stats));
+            if let Err(e) = result {
+                log::warn!("Metrics request failed: {}", e);
+            }
+        }
+    });
+    Ok(())
+}
+
+fn serve(stream: TcpStream, stats: &Stats) -> std::io::Result<()> {
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+    // Any request gets the metrics, only drain the headers.
+    let mut reader = BufReader::new((&stream).take(MAX_REQUEST));
+    let mut line = String::new();
+    while reader.read_line(&mut line)? > 2 {
+        line.clear();
+    }
+
+    let body = render(stats);
+    let mut stream = &stream;
+    write!(
+        stream,
+        "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        body.len(),
+        body
+    )
+}
+
+fn render(stats: &Stats) -> String {
+    let mut out = String::new();
+    let backend = escape(&stats.backend);
+    let devices = stats.devices();
+
+    header(&mut out, "ygglkan_keys_total", "counter", "Keys generated");
+    for d in devices.iter() {
+        let keys = d.keys.load(Ordering::Relaxed);
+        let device = escape(&d.name);
+        writeln!(
+            out,
+            "ygglkan_keys_total{{backend=\"{backend}\",device=\"{device}\"}} {keys}"
+        )
+        .unwrap();
+    }
+
+    header(
+        &mut out,
+        "ygglkan_batch_duration_seconds",
+        "summary",
+        "Time per batch",
+    );
+    for d in devices.iter() {
+        let device = escape(&d.name);
+        let sum = d.batch_nanos.load(Ordering::Relaxed) as f64 / 1e9;
+        let count = d.batches.load(Ordering::Relaxed);
+        writeln!(
+            out,
+            "ygglkan_batch_duration_seconds_sum{{device=\"{device}\"}} {sum}"
+        )
+        .unwrap();
+        writeln!(
+            out,
+            "ygglkan_batch_duration_seconds_count{{device=\"{device}\"}} {count}"
+        )
+        .unwrap();
+    }
+
+    header(
+        &mut out,
+        "ygglkan_hashrate_keys_per_second",
+        "gauge",
+        "Moving-average keys per second",
+    );
+    writeln!(out, "ygglkan_hashrate_keys_per_second {}", stats.hashrate()).unwrap();
+
+    let patterns = stats.patterns();
+    header(
+        &mut out,
+        "ygglkan_results_total",
+        "counter",
+        "Results reported",
+    );
+    // Indexed, as patterns may share a name.
+    for (index, p) in patterns.iter().enumerate() {
+        let found = p.found.load(Ordering::Relaxed);
+        writeln!(
+            out,
+            "ygglkan_results_total{{index=\"{index}\",pattern=\"{}\"}} {found}",
+            escape(&p.pattern)
+        )
+        .unwrap();
+    }
+
+    header(
+        &mut out,
+        "ygglkan_best_height",
+        "gauge",
+        "Best height found",
+    );
+    for (index, p) in patterns.iter().enumerate() {
+        let height = p.best_height.load(Ordering::Relaxed);
+        writeln!(
+            out,
+            "ygglkan_best_height{{index=\"{index}\",pattern=\"{}\"}} {height}",
+            escape(&p.pattern)
+        )
+        .unwrap();
+    }
+
+    header(
+        &mut out,
+        "ygglkan_verification_failures_total",
+        "counter",
+        "Results whose public key did not match the seed",
+    );
+    let failures = stats.verification_failures.load(Ordering::Relaxed);
+    writeln!(out, "ygglkan_verification_failures_total {failures}").unwrap();
+
+    out
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    writeln!(out, "# HELP {name} {help}").unwrap();
+    writeln!(out, "# TYPE {name} {kind}").unwrap();
+}
+
+fn escape(label: &str) -> String {
+    label
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::render;
+    use crate::{patterns, Backend, Miner};
+
+    #[test]
+    fn test_render() {
+        let regexes = [String::from("^2"), String::from("^2")];
+        let miner = Miner::builder()
+            .backend(Backend::Cpu)
+            .patterns(patterns::from_args(&regexes, Default::default()).unwrap())
+            .build()
+            .unwrap();
+        miner.stats().register("cpu");
+        let out = render(miner.stats());
+
+        let mut series = HashSet::new();
+        for line in out.lines().filter(|l| !l.starts_with('#')) {
+            let (name, value) = line.rsplit_once(' ').unwrap();
+            assert!(value.parse::<f64>().is_ok(), "{}", line);
+            assert!(series.insert(name), "duplicate {}", name);
+        }
+        for index in 0..2 {
+            let name = format!(
+                "ygglkan_results_total{{index=\"{}\",pattern=\"^2\"}}",
+                index
+            );
+            assert!(series.contains(name.as_str()), "{}", out);
+        }
+        assert!(series.contains("ygglkan_keys_total{backend=\"CPU\",device=\"cpu\"}"));
+    }
+}
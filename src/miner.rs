@@ -0,0 +1,396 @@
+//! Embedding API: configure a [`Miner`] with [`MinerBuilder`], run it on a
+//! thread of your choice and receive results through a [`ResultSink`].
+
+use std::{
+    net::Ipv6Addr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, RwLock,
+    },
+    time::Duration,
+};
+
+use futures::channel::mpsc;
+use zeroize::Zeroizing;
+
+use crate::{
+    backend::{self, Backend},
+    error::Error,
+    handler,
+    patterns::{self, Pattern, PatternSet},
+    seeds::{RandomSeeds, SeedSource},
+    stats::{Report, Stats},
+    stream::{ChannelSink, MinerStream},
+    PublicKey, Seed,
+};
+
+/// A verified key matching a pattern.
+pub struct Hit<'a> {
+    pub seed: &'a Seed,
+    pub public_key: &'a PublicKey,
+    pub address: Ipv6Addr,
+    pub height: u8,
+    pub pattern: &'a Pattern,
+    pub score: u32,
+    /// Leaderboard rank, if matches are ranked.
+    pub rank: Option<usize>,
+}
+
+impl Hit<'_> {
+    /// Seed followed by public key, the form Yggdrasil configs store.
+    pub fn private_key(&self) -> Zeroizing<[u8; 64]> {
+        let mut sk = Zeroizing::new([0u8; 64]);
+        sk[..32].copy_from_slice(self.seed);
+        sk[32..].copy_from_slice(self.public_key);
+        sk
+    }
+}
+
+/// Receives hits from mining threads.
+pub trait ResultSink: Send + Sync {
+    /// Called for every reported hit. An error stops the miner.
+    fn found(&self, hit: &Hit) -> Result<(), Error>;
+}
+
+impl<F: Fn(&Hit) -> Result<(), Error> + Send + Sync> ResultSink for F {
+    fn found(&self, hit: &Hit) -> Result<(), Error> {
+        self(hit)
+    }
+}
+
+/// When a run ends on its own.
+#[derive(Debug, Default, Clone, Copy)]
+struct Limits {
+    duration: Option<Duration>,
+    results: Option<u64>,
+    keys: Option<u64>,
+}
+
+pub struct MinerBuilder {
+    backend: Backend,
+    device: Option<usize>,
+    batch_size: usize,
+    patterns: Vec<Pattern>,
+    excludes: Vec<Pattern>,
+    words: Option<Vec<String>>,
+    report: Report,
+    limits: Limits,
+    sink: Option<Box<dyn ResultSink>>,
+    seeds: Box<dyn SeedSource>,
+}
+
+impl Default for MinerBuilder {
+    fn default() -> Self {
+        Self {
+            backend: Backend::Gpu,
+            device: None,
+            batch_size: 1024,
+            patterns: Vec::new(),
+            excludes: Vec::new(),
+            words: None,
+            report: Report::Best,
+            limits: Limits::default(),
+            sink: None,
+            seeds: Box::new(RandomSeeds),
+        }
+    }
+}
+
+impl MinerBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn backend(mut self, backend: Backend) -> Self {
+        self.backend = backend;
+        self
+    }
+
+    /// Index of the GPU to use among Vulkan adapters, instead of the most
+    /// powerful one.
+    pub fn device(mut self, index: usize) -> Self {
+        self.device = Some(index);
+        self
+    }
+
+    /// GPU batch size in blocks of 64 keys.
+    pub fn batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size;
+        self
+    }
+
+    /// Patterns to search for; every address matches if none are given.
+    pub fn patterns(mut self, patterns: Vec<Pattern>) -> Self {
+        self.patterns = patterns;
+        self
+    }
+
+    /// Addresses matching any of these are skipped.
+    pub fn excludes(mut self, excludes: Vec<Pattern>) -> Self {
+        self.excludes = excludes;
+        self
+    }
+
+    /// Words counted by the words score instead of the built-in list.
+    pub fn words(mut self, words: Vec<String>) -> Self {
+        self.words = Some(words);
+        self
+    }
+
+    pub fn report(mut self, report: Report) -> Self {
+        self.report = report;
+        self
+    }
+
+    /// Stops after mining for `duration`.
+    pub fn stop_after(mut self, duration: Duration) -> Self {
+        self.limits.duration = Some(duration);
+        self
+    }
+
+    /// Stops after reporting `results` hits.
+    pub fn stop_after_results(mut self, results: u64) -> Self {
+        self.limits.results = Some(results);
+        self
+    }
+
+    /// Stops after generating `keys` keys.
+    pub fn stop_after_keys(mut self, keys: u64) -> Self {
+        self.limits.keys = Some(keys);
+        self
+    }
+
+    /// Where hits go; without a sink they are only counted.
+    pub fn sink(mut self, sink: impl ResultSink + 'static) -> Self {
+        self.sink = Some(Box::new(sink));
+        self
+    }
+
+    /// Where seeds come from, random ones by default. Mining ends when the
+    /// source runs out.
+    pub fn seeds(mut self, seeds: impl SeedSource + 'static) -> Self {
+        self.seeds = Box::new(seeds);
+        self
+    }
+
+    pub fn build(self) -> Result<Miner, Error> {
+        if self.batch_size == 0 {
+            return Err(Error::Limits(String::from("batch size must not be zero")));
+        }
+        let mut patterns = self.patterns;
+        if patterns.is_empty() {
+            patterns = patterns::from_args(&[String::from("")], Default::default())?;
+        }
+        if let Report::AtLeast(height) = self.report {
+            for pattern in &mut patterns {
+                pattern.min_height = pattern.min_height.max(height);
+            }
+        }
+        let words = match self.words {
+            Some(words) => words,
+            None => crate::score::load_words(None)?,
+        };
+
+        let stats = Arc::new(Stats::new(
+            self.backend.to_string(),
+            patterns.iter().map(|p| p.name.clone()),
+            self.report.top(),
+        ));
+        Ok(Miner {
+            backend: self.backend,
+            device: self.device,
+            batch_size: self.batch_size,
+            report: self.report,
+            context: Context {
+                patterns: RwLock::new(PatternSet::new(patterns, self.excludes, words)?),
+                stats,
+                sink: self.sink,
+                seeds: Mutex::new(self.seeds),
+                stop: AtomicBool::new(false),
+                paused: AtomicBool::new(false),
+                error: Mutex::new(None),
+                limits: self.limits,
+            },
+        })
+    }
+}
+
+fn no_pattern(index: usize) -> Error {
+    Error::Pattern(format!("no pattern number {}", index))
+}
+
+/// Everything mining threads share.
+pub(crate) struct Context {
+    /// Read-locked for a whole batch, so added patterns apply from the next.
+    pub patterns: RwLock<PatternSet>,
+    pub stats: Arc<Stats>,
+    pub sink: Option<Box<dyn ResultSink>>,
+    pub seeds: Mutex<Box<dyn SeedSource>>,
+    stop: AtomicBool,
+    paused: AtomicBool,
+    /// First error of a mining thread, returned by [`Miner::run`].
+    error: Mutex<Option<Error>>,
+    limits: Limits,
+}
+
+impl Context {
+    pub fn stop(&self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+
+    /// Stops mining because of `error`.
+    pub fn fail(&self, error: Error) {
+        self.error.lock().unwrap().get_or_insert(error);
+        self.stop();
+    }
+
+    pub fn paused(&self) -> bool {
+        self.paused.load(Ordering::Relaxed)
+    }
+
+    /// Whether mining threads should wind down, checked once per batch.
+    pub fn stopping(&self) -> bool {
+        if self.stop.load(Ordering::Relaxed) {
+            return true;
+        }
+        let limits = &self.limits;
+        let reached = limits.duration.map_or(false, |d| self.stats.uptime() >= d)
+            || limits.results.map_or(false, |r| self.stats.found() >= r)
+            || limits.keys.map_or(false, |k| self.stats.keys() >= k);
+        if reached {
+            self.stop();
+        }
+        reached
+    }
+}
+
+/// A configured miner. Share it between threads to stop it while it runs.
+pub struct Miner {
+    backend: Backend,
+    device: Option<usize>,
+    batch_size: usize,
+    report: Report,
+    context: Context,
+}
+
+impl Miner {
+    pub fn builder() -> MinerBuilder {
+        MinerBuilder::new()
+    }
+
+    pub fn stats(&self) -> &Arc<Stats> {
+        &self.context.stats
+    }
+
+    /// Mines on a thread of its own, delivering hits to the returned stream
+    /// instead of the configured sink, along with stats every
+    /// `stats_interval`, at least [`crate::stream::MIN_STATS_INTERVAL`].
+    /// Dropping the stream stops mining.
+    pub fn stream(mut self, stats_interval: Duration) -> MinerStream {
+        let (sender, receiver) = mpsc::unbounded();
+        self.context.sink = Some(Box::new(ChannelSink(sender.clone())));
+        MinerStream::spawn(Arc::new(self), sender, receiver, stats_interval)
+    }
+
+    /// Mines until stopped, a stop condition is met, the seed source runs
+    /// out or an error occurs.
+    /// Seed buffers are wiped before returning. A lost GPU is set up again a
+    /// few times before giving up.
+    pub fn run(&self) -> Result<(), Error> {
+        let (backend, batch_size, device) = (self.backend, self.batch_size, self.device);
+        backend::drive(|| backend.create(batch_size, device), &self.context)?;
+        match self.context.error.lock().unwrap().take() {
+            Some(error) => Err(error),
+            None => Ok(()),
+        }
+    }
+
+    /// Checks a seed found elsewhere, such as by a worker, and reports it
+    /// like a mined one if it matches.
+    pub fn check(&self, seed: &Seed) {
+        let public_key = crate::cpu::dalek_public_from_seed(seed);
+        let patterns = self.context.patterns.read().unwrap();
+        handler::handle_keypair(seed, &public_key, &patterns, &self.context);
+    }
+
+    /// Index for the default output tag of the next pattern added, see
+    /// [`patterns::from_arg`].
+    pub fn next_pattern_index(&self) -> usize {
+        self.context.patterns.read().unwrap().next_index()
+    }
+
+    /// Calls `f` with the patterns searched for, holding mining threads
+    /// back until it returns.
+    pub fn with_patterns<R>(&self, f: impl FnOnce(&[Pattern]) -> R) -> R {
+        f(&self.context.patterns.read().unwrap().patterns)
+    }
+
+    /// Searches for `patterns` too from the next batch on. Their results
+    /// are numbered after the ones already searched for.
+    pub fn add_patterns(&self, mut patterns: Vec<Pattern>) -> Result<(), Error> {
+        for pattern in &mut patterns {
+            self.apply_report(pattern);
+        }
+        let names: Vec<_> = patterns.iter().map(|p| p.name.clone()).collect();
+        let mut set = self.context.patterns.write().unwrap();
+        set.extend(patterns)?;
+        for name in names {
+            self.context.stats.add_pattern(name);
+        }
+        Ok(())
+    }
+
+    /// Searches for `pattern` instead of pattern number `index` from the
+    /// next batch on. Its results go to the output file of the old one,
+    /// and its stats start over.
+    pub fn replace_pattern(&self, index: usize, mut pattern: Pattern) -> Result<(), Error> {
+        self.apply_report(&mut pattern);
+        let mut set = self.context.patterns.write().unwrap();
+        let old = set.patterns.get(index).ok_or_else(|| no_pattern(index))?;
+        pattern.output = old.output.clone();
+        let name = pattern.name.clone();
+        set.replace(index, pattern)?;
+        self.context.stats.replace_pattern(index, name);
+        Ok(())
+    }
+
+    /// Stops searching for pattern number `index` from the next batch on.
+    /// Patterns after it move down.
+    pub fn remove_pattern(&self, index: usize) -> Result<(), Error> {
+        let mut set = self.context.patterns.write().unwrap();
+        if index >= set.patterns.len() {
+            return Err(no_pattern(index));
+        }
+        set.remove(index)?;
+        self.context.stats.remove_pattern(index);
+        Ok(())
+    }
+
+    fn apply_report(&self, pattern: &mut Pattern) {
+        if let Report::AtLeast(height) = self.report {
+            pattern.min_height = pattern.min_height.max(height);
+        }
+    }
+
+    /// Holds mining threads before their next batch until resumed.
+    pub fn pause(&self) {
+        self.context.paused.store(true, Ordering::Relaxed);
+    }
+
+    pub fn resume(&self) {
+        self.context.paused.store(false, Ordering::Relaxed);
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.context.paused()
+    }
+
+    /// Asks [`Miner::run`] to return after the current batch.
+    pub fn stop(&self) {
+        self.context.stop();
+    }
+
+    pub fn is_stopping(&self) -> bool {
+        self.context.stopping()
+    }
+}
@@ -0,0 +1,383 @@
+use std::{
+    borrow::Cow,
+    fmt::Write as _,
+    fs::{File, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use hex::ToHex;
+use zeroize::Zeroizing;
+
+use crate::{
+    crypto::{self, Cipher, Decryptor},
+    error::{self, Error},
+    export,
+    miner::{Hit, ResultSink},
+    score::Score,
+    PublicKey, Seed,
+};
+
+/// Where found keys are stored.
+enum Sink {
+    /// Print everything to stdout.
+    Stdout,
+    /// Append all results to a single file.
+    File(PathBuf),
+    /// Append results to a file per pattern in a directory.
+    Dir(PathBuf),
+}
+
+/// Saves found keys to a sink, optionally encrypting them.
+pub struct Output {
+    sink: Sink,
+    cipher: Option<Cipher>,
+    export_dir: Option<PathBuf>,
+    /// Don't print where results were saved.
+    quiet: bool,
+}
+
+/// Serializes appends from the handler threads.
+static WRITE_LOCK: Mutex<()> = Mutex::new(());
+
+impl Output {
+    pub fn new(
+        file: Option<PathBuf>,
+        dir: Option<PathBuf>,
+        cipher: Option<Cipher>,
+        export_dir: Option<PathBuf>,
+    ) -> Result<Self, Error> {
+        let sink = match (file, dir) {
+            (Some(file), _) => {
+                // Fails at startup rather than on the first result.
+                open_private(&file).map_err(error::io(file.display()))?;
+                Sink::File(file)
+            }
+            (None, Some(dir)) => {
+                create_private_dir(&dir).map_err(error::io(dir.display()))?;
+                Sink::Dir(dir)
+            }
+            (None, None) => Sink::Stdout,
+        };
+        if let Some(dir) = &export_dir {
+            create_private_dir(dir).map_err(error::io(dir.display()))?;
+        }
+        Ok(Self {
+            sink,
+            cipher,
+            export_dir,
+            quiet: false,
+        })
+    }
+
+    /// Saves results without printing anything, for when the terminal
+    /// shows something else. Results only printed are still printed.
+    pub fn quiet(mut self) -> Self {
+        self.quiet = true;
+        self
+    }
+
+    /// File results of patterns with output tag `tag` are written to.
+    pub fn path(&self, tag: &str) -> Option<PathBuf> {
+        match &self.sink {
+            Sink::Stdout => None,
+            Sink::File(file) => Some(file.clone()),
+            Sink::Dir(dir) => Some(dir.join(format!("{}.txt", tag))),
+        }
+    }
+
+    /// Appends `record`, encrypted if a cipher is set, with a single write
+    /// and syncs it to disk.
+    pub fn append(&self, path: &Path, record: &str) -> std::io::Result<()> {
+        let record = self.sealed(record);
+        let _lock = WRITE_LOCK.lock().unwrap();
+        let mut file = open_private(path)?;
+        file.write_all(record.as_bytes())?;
+        file.sync_data()
+    }
+
+    /// `record` as stored, encrypted if a cipher is set.
+    fn sealed<'a>(&self, record: &'a str) -> Cow<'a, str> {
+        match &self.cipher {
+            Some(cipher) => Cow::Owned(cipher.encrypt(record)),
+            None => Cow::Borrowed(record),
+        }
+    }
+
+    /// Saves `record` of `address`, which couldn't be appended to `path`, to
+    /// a new private file in the temporary directory, telling where on
+    /// stderr. The record itself isn't printed, as stderr may end up in logs.
+    fn rescue(&self, record: &str, address: &str, path: &Path) {
+        let rescue = std::env::temp_dir().join(format!(
+            "ygglkan-unsaved-{}-{:016x}.txt",
+            std::process::id(),
+            rand::random::<u64>()
+        ));
+        let mut options = OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        let result = options.open(&rescue).and_then(|mut file| {
+            file.write_all(self.sealed(record).as_bytes())?;
+            file.sync_data()
+        });
+        match result {
+            Ok(()) => eprintln!(
+                "Saving {} to {} failed, saved it to {} instead",
+                address,
+                path.display(),
+                rescue.display()
+            ),
+            Err(e) => eprintln!(
+                "Saving {} to {} failed, and so did saving it to {}: {}",
+                address,
+                path.display(),
+                rescue.display(),
+                e
+            ),
+        }
+    }
+
+    /// Exports a keypair in OpenSSH and PKCS#8 formats if an export
+    /// directory is set, returning the path of the OpenSSH private key.
+    pub fn export(
+        &self,
+        seed: &Seed,
+        pk: &PublicKey,
+        address: &str,
+    ) -> Result<Option<PathBuf>, Error> {
+        let Some(dir) = &self.export_dir else {
+            return Ok(None);
+        };
+        let base = dir.join(export::file_name(address));
+        export::export(&base, seed, pk, address)?;
+        Ok(Some(base))
+    }
+}
+
+/// Formats `hit` as a results file record.
+pub fn record(hit: &Hit) -> Zeroizing<String> {
+    let sk_hex = Zeroizing::new(hit.private_key().as_slice().encode_hex::<String>());
+    let mut record = Zeroizing::new(String::new());
+    writeln!(record, "=======================================").unwrap();
+    writeln!(record, "PrivateKey: {}", *sk_hex).unwrap();
+    writeln!(
+        record,
+        "PublicKey: {}",
+        hit.public_key.encode_hex::<String>()
+    )
+    .unwrap();
+    writeln!(record, "Address: {}", hit.address).unwrap();
+    writeln!(record, "Height: {}", hit.height).unwrap();
+    writeln!(record, "Pattern: {}", hit.pattern.name).unwrap();
+    if hit.pattern.score != Score::Height {
+        writeln!(record, "Score: {} ({})", hit.score, hit.pattern.score).unwrap();
+    }
+    writeln!(record, "=======================================").unwrap();
+    record
+}
+
+impl ResultSink for Output {
+    /// Saves or prints the record of `hit` and exports its key.
+    fn found(&self, hit: &Hit) -> Result<(), Error> {
+        let record = record(hit);
+        let address = hit.address.to_string();
+        let mut lock = std::io::stdout().lock();
+        match self.path(&hit.pattern.output) {
+            Some(path) => {
+                if let Err(e) = self.append(&path, &record) {
+                    self.rescue(&record, &address, &path);
+                    return Err(Error::Io(path.display().to_string(), e));
+                }
+                if self.quiet {
+                    return self.export(hit.seed, hit.public_key, &address).map(drop);
+                }
+                let rank = hit
+                    .rank
+                    .map(|r| format!(", rank {}", r))
+                    .unwrap_or_default();
+                writeln!(
+                    lock,
+                    "Found {} (height {}, {} {}{}) for {}, saved to {}",
+                    address,
+                    hit.height,
+                    hit.pattern.score,
+                    hit.score,
+                    rank,
+                    hit.pattern.name,
+                    path.display()
+                )
+                .map_err(error::io("stdout"))?;
+            }
+            None => lock
+                .write_all(record.as_bytes())
+                .map_err(error::io("stdout"))?,
+        }
+        if let Some(base) = self.export(hit.seed, hit.public_key, &address)? {
+            writeln!(lock, "Exported {} to {}", address, base.display())
+                .map_err(error::io("stdout"))?;
+        }
+        Ok(())
+    }
+}
+
+/// Reads records from a results file, decrypting encrypted ones with the
+/// passphrase `passphrase` returns, called on first use.
+pub fn read_records(
+    path: &Path,
+    passphrase: impl FnOnce() -> Result<Zeroizing<String>, Error>,
+) -> Result<Vec<Zeroizing<String>>, Error> {
+    let contents =
+        Zeroizing::new(std::fs::read_to_string(path).map_err(error::io(path.display()))?);
+    let mut passphrase = Some(passphrase);
+    let mut decryptor = None;
+    let mut records = Vec::new();
+    let mut plain = Zeroizing::new(String::new());
+
+    for (n, line) in contents.lines().enumerate() {
+        if crypto::is_encrypted(line) {
+            if let Some(passphrase) = passphrase.take() {
+                decryptor = Some(Decryptor::new(passphrase()?));
+            }
+            let record = decryptor
+                .as_mut()
+                .unwrap()
+                .decrypt(line)
+                .map_err(|e| Error::Key(format!("{}:{}: {}", path.display(), n + 1, e)))?;
+            records.push(record);
+            continue;
+        }
+
+        plain.push_str(line);
+        plain.push('\n');
+        // Plaintext records are framed by separator lines.
+        if line.starts_with('=') && plain.lines().count() > 1 {
+            records.push(Zeroizing::new(std::mem::take(&mut *plain)));
+        }
+    }
+    Ok(records)
+}
+
+/// Prints records of a results file, hiding private keys unless `reveal` is set.
+pub fn show(path: &Path, reveal: bool) -> Result<(), Error> {
+    let mut stdout = std::io::stdout().lock();
+    for record in read_records(path, || crypto::read_passphrase(false))? {
+        for line in record.lines() {
+            if reveal || !line.starts_with("PrivateKey:") {
+                writeln!(stdout, "{}", line).map_err(error::io("stdout"))?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn open_private(path: &Path) -> std::io::Result<File> {
+    let mut options = OpenOptions::new();
+    options.append(true).create(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    options.open(path)
+}
+
+/// Replaces the contents of `path`, creating it with permissions `mode` on
+/// Unix.
+#[cfg_attr(not(unix), allow(unused_variables))]
+pub fn write_file(path: &Path, contents: &[u8], mode: u32) -> std::io::Result<()> {
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(mode);
+    }
+    let mut file = options.open(path)?;
+    // The mode above only applies to new files and is masked by the umask.
+    // Set before writing, so an existing file's contents are never exposed.
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        file.set_permissions(std::fs::Permissions::from_mode(mode))?;
+    }
+    file.write_all(contents)?;
+    file.sync_data()
+}
+
+pub fn create_private_dir(dir: &Path) -> std::io::Result<()> {
+    let mut builder = std::fs::DirBuilder::new();
+    builder.recursive(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::DirBuilderExt;
+        builder.mode(0o700);
+    }
+    builder.create(dir)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use zeroize::Zeroizing;
+
+    use super::{read_records, Output};
+    use crate::crypto::Cipher;
+
+    /// A path in the temporary directory no other test run uses.
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!(
+            "ygglkan-test-{}-{}-{:016x}",
+            name,
+            std::process::id(),
+            rand::random::<u64>()
+        ))
+    }
+
+    #[test]
+    fn test_append() {
+        let dir = temp_path("append");
+        let output = Output::new(None, Some(dir.clone()), None, None).unwrap();
+        let path = output.path("pattern-3").unwrap();
+
+        output.append(&path, "first\n").unwrap();
+        output.append(&path, "second\n").unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "first\nsecond\n");
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_read_records() {
+        let record = "=====\nPrivateKey: 00\nAddress: 200::\n=====\n";
+        let path = temp_path("records");
+
+        Output::new(Some(path.clone()), None, None, None)
+            .unwrap()
+            .append(&path, record)
+            .unwrap();
+        Output::new(Some(path.clone()), None, Some(Cipher::new("secret")), None)
+            .unwrap()
+            .append(&path, record)
+            .unwrap();
+
+        let records = read_records(&path, || Ok(Zeroizing::new(String::from("secret")))).unwrap();
+        assert_eq!(
+            records.iter().map(|r| r.as_str()).collect::<Vec<_>>(),
+            vec![record, record]
+        );
+        std::fs::remove_file(path).unwrap();
+    }
+}
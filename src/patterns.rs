@@ -0,0 +1,840 @@
+use std::{net::Ipv6Addr, path::Path, str::FromStr};
+
+use regex::{Regex, RegexSet};
+use regex_syntax::hir::{literal::Extractor, Look};
+use serde::Deserialize;
+use strum_macros::{Display, EnumString};
+
+use crate::{
+    error::{self, Error},
+    estimate::Prefix,
+    expr::Expr,
+    score::Score,
+    PublicKey,
+};
+
+/// How the pattern text of an entry is interpreted.
+#[derive(Deserialize, Debug, Default, Clone, Copy)]
+#[serde(rename_all = "kebab-case")]
+enum Kind {
+    #[default]
+    Regex,
+    Prefix,
+    Expr,
+}
+
+/// Text regexes are matched against.
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq, EnumString, Display)]
+#[serde(rename_all = "kebab-case")]
+#[strum(serialize_all = "kebab-case", ascii_case_insensitive)]
+pub enum MatchTarget {
+    /// Address as usually printed, e.g. `200:1234::1`.
+    #[default]
+    Compressed,
+    /// All 8 groups of 4 digits, e.g. `0200:1234:0000:0000:0000:0000:0000:0001`.
+    Expanded,
+    /// 32 hex digits of the address without separators.
+    Raw,
+    /// 64 hex digits of the public key.
+    PublicKey,
+}
+
+impl MatchTarget {
+    const ALL: [Self; 4] = [Self::Compressed, Self::Expanded, Self::Raw, Self::PublicKey];
+
+    pub fn text(self, addr: &Ipv6Addr, pk: &PublicKey) -> String {
+        match self {
+            Self::Compressed => addr.to_string(),
+            Self::Expanded => {
+                let groups: Vec<_> = addr
+                    .segments()
+                    .iter()
+                    .map(|g| format!("{:04x}", g))
+                    .collect();
+                groups.join(":")
+            }
+            Self::Raw => hex::encode(addr.octets()),
+            Self::PublicKey => hex::encode(pk),
+        }
+    }
+}
+
+/// Settings of patterns that don't set their own.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Defaults {
+    pub target: MatchTarget,
+    pub score: Score,
+}
+
+/// A generated key being checked against patterns, with texts of match
+/// targets formatted on first use.
+pub struct Candidate<'a> {
+    pub addr: &'a Ipv6Addr,
+    pub pk: &'a PublicKey,
+    pub height: u8,
+    texts: [Option<String>; 4],
+}
+
+impl<'a> Candidate<'a> {
+    pub fn new(addr: &'a Ipv6Addr, pk: &'a PublicKey, height: u8) -> Self {
+        Self {
+            addr,
+            pk,
+            height,
+            texts: Default::default(),
+        }
+    }
+
+    pub fn text(&mut self, target: MatchTarget) -> &str {
+        let (addr, pk) = (self.addr, self.pk);
+        self.texts[target as usize].get_or_insert_with(|| target.text(addr, pk))
+    }
+}
+
+pub enum Matcher {
+    /// Regex matched against the text selected by `target`.
+    Regex(Regex, MatchTarget),
+    /// IPv6 prefix in `addr/len` notation.
+    Prefix(Prefix),
+    /// Boolean expression, see [`crate::expr`].
+    Expr(Expr),
+}
+
+/// A pattern to mine addresses for.
+pub struct Pattern {
+    /// Shown in output and metrics.
+    pub name: String,
+    pub matcher: Matcher,
+    /// Addresses with a lower height are ignored.
+    pub min_height: u8,
+    /// How results are ranked.
+    pub score: Score,
+    /// File name results are saved to with `--output-dir`.
+    pub output: String,
+}
+
+impl Pattern {
+    pub fn is_match(&self, candidate: &mut Candidate) -> bool {
+        match &self.matcher {
+            Matcher::Regex(re, target) => re.is_match(candidate.text(*target)),
+            Matcher::Prefix(prefix) => prefix.contains(candidate.addr),
+            Matcher::Expr(expr) => expr.is_match(candidate),
+        }
+    }
+}
+
+/// All patterns of a run, matched against an address in a single pass.
+pub struct PatternSet {
+    pub patterns: Vec<Pattern>,
+    /// Regexes of every used match target, with pattern indices of the regexes.
+    regexes: Vec<(MatchTarget, RegexSet, Vec<usize>)>,
+    /// Regexes no matching key may match.
+    excludes: Vec<(MatchTarget, RegexSet, Vec<usize>)>,
+    /// Lowest `min_height` of all patterns.
+    min_height: u8,
+    /// Prefixes at least one of which every matching address starts with,
+    /// if such prefixes are known for every pattern.
+    prefilter: Option<Vec<Prefix>>,
+    /// Dictionary of [`Score::Words`].
+    words: Vec<String>,
+    /// Patterns ever added, including removed ones.
+    numbered: usize,
+}
+
+impl PatternSet {
+    /// Keys matching any regex of `excludes` never match.
+    pub fn new(
+        patterns: Vec<Pattern>,
+        excludes: Vec<Pattern>,
+        words: Vec<String>,
+    ) -> Result<Self, Error> {
+        let mut set = Self {
+            patterns: Vec::new(),
+            regexes: Vec::new(),
+            excludes: regex_sets(&excludes)?,
+            min_height: 0,
+            prefilter: Some(Vec::new()),
+            words,
+            numbered: 0,
+        };
+        set.extend(patterns)?;
+        Ok(set)
+    }
+
+    /// Adds patterns after the ones already in the set. The set is left as
+    /// it was if the combined regexes are too large.
+    pub fn extend(&mut self, patterns: Vec<Pattern>) -> Result<(), Error> {
+        let (count, added) = (self.patterns.len(), patterns.len());
+        self.patterns.extend(patterns);
+        if let Err(e) = self.index() {
+            self.patterns.truncate(count);
+            return Err(e);
+        }
+        self.numbered += added;
+        Ok(())
+    }
+
+    /// Puts `pattern` in place of pattern number `index`, returning the old
+    /// one. The set is left as it was if the combined regexes are too large.
+    pub fn replace(&mut self, index: usize, pattern: Pattern) -> Result<Pattern, Error> {
+        let old = std::mem::replace(&mut self.patterns[index], pattern);
+        if let Err(e) = self.index() {
+            self.patterns[index] = old;
+            return Err(e);
+        }
+        Ok(old)
+    }
+
+    /// Removes pattern number `index`, moving the ones after it down.
+    pub fn remove(&mut self, index: usize) -> Result<Pattern, Error> {
+        let pattern = self.patterns.remove(index);
+        if let Err(e) = self.index() {
+            self.patterns.insert(index, pattern);
+            return Err(e);
+        }
+        Ok(pattern)
+    }
+
+    /// Number for the default output tag of the next pattern added, so tags
+    /// of removed patterns aren't reused.
+    pub fn next_index(&self) -> usize {
+        self.numbered
+    }
+
+    /// Recomputes what speeds up matching after `patterns` changed, leaving
+    /// everything as it was on errors.
+    fn index(&mut self) -> Result<(), Error> {
+        self.regexes = regex_sets(&self.patterns)?;
+
+        let mut prefilter = Some(Vec::new());
+        for pattern in &self.patterns {
+            let prefixes = match &pattern.matcher {
+                Matcher::Regex(re, target) => literal_prefixes(re.as_str(), *target),
+                Matcher::Prefix(prefix) => Some(vec![prefix.clone()]),
+                Matcher::Expr(expr) => expr.prefixes(),
+            };
+            match (&mut prefilter, prefixes) {
+                (Some(all), Some(prefixes)) => all.extend(prefixes),
+                _ => prefilter = None,
+            }
+        }
+        self.prefilter = prefilter;
+        self.min_height = self
+            .patterns
+            .iter()
+            .map(|p| p.min_height)
+            .min()
+            .unwrap_or(0);
+        Ok(())
+    }
+
+    /// Whether results of all patterns are ranked by height, so keys below
+    /// the lowest leaderboard threshold can be skipped right away.
+    pub fn scores_by_height(&self) -> bool {
+        self.patterns.iter().all(|p| p.score == Score::Height)
+    }
+
+    /// Score of the key for pattern number `pattern`.
+    pub fn score(&self, pattern: usize, candidate: &mut Candidate) -> u32 {
+        self.patterns[pattern].score.compute(candidate, &self.words)
+    }
+
+    /// Cheap check whether a key of height `height` with address `addr` can
+    /// match any pattern, done before the address is formatted.
+    pub fn may_match(&self, addr: &Ipv6Addr, height: u8) -> bool {
+        if height < self.min_height {
+            return false;
+        }
+        match &self.prefilter {
+            Some(prefixes) => prefixes.iter().any(|p| p.contains(addr)),
+            None => true,
+        }
+    }
+
+    /// Indices of patterns matching the key, in ascending order.
+    pub fn matches(&self, candidate: &mut Candidate) -> Vec<usize> {
+        for (target, set, _) in &self.excludes {
+            if set.is_match(candidate.text(*target)) {
+                return Vec::new();
+            }
+        }
+
+        let mut matches = Vec::new();
+        for (target, set, indices) in &self.regexes {
+            let text = candidate.text(*target);
+            matches.extend(set.matches(text).into_iter().map(|i| indices[i]));
+        }
+        for (i, pattern) in self.patterns.iter().enumerate() {
+            if !matches!(pattern.matcher, Matcher::Regex(..)) && pattern.is_match(candidate) {
+                matches.push(i);
+            }
+        }
+        matches.sort_unstable();
+        matches
+    }
+}
+
+/// Groups regexes of `patterns` by match target, along with their pattern
+/// indices. Fails if the combined regexes are too large.
+fn regex_sets(patterns: &[Pattern]) -> Result<Vec<(MatchTarget, RegexSet, Vec<usize>)>, Error> {
+    MatchTarget::ALL
+        .into_iter()
+        .filter_map(|target| {
+            let (indices, regexes): (Vec<_>, Vec<_>) = patterns
+                .iter()
+                .enumerate()
+                .filter_map(|(i, p)| match &p.matcher {
+                    Matcher::Regex(re, t) if *t == target => Some((i, re.as_str())),
+                    _ => None,
+                })
+                .unzip();
+            if indices.is_empty() {
+                return None;
+            }
+            let set = RegexSet::new(regexes).map_err(|e| Error::Pattern(e.to_string()));
+            Some(set.map(|set| (target, set, indices)))
+        })
+        .collect()
+}
+
+/// Address prefixes implied by the literal start of an anchored regex.
+pub fn literal_prefixes(regex: &str, target: MatchTarget) -> Option<Vec<Prefix>> {
+    let literal_prefix: fn(&str) -> Option<Prefix> = match target {
+        MatchTarget::Compressed => compressed_prefix,
+        MatchTarget::Expanded => |literal: &str| hex_prefix(literal, true),
+        MatchTarget::Raw => |literal: &str| hex_prefix(literal, false),
+        MatchTarget::PublicKey => return None,
+    };
+    let hir = regex_syntax::parse(regex).ok()?;
+    if !hir.properties().look_set_prefix().contains(Look::Start) {
+        return None;
+    }
+    Extractor::new()
+        .extract(&hir)
+        .literals()?
+        .iter()
+        .map(|literal| literal_prefix(std::str::from_utf8(literal.as_bytes()).ok()?))
+        .collect()
+}
+
+/// Complete groups of a literal like `200:1234:` fix the first 32 address
+/// bits, as no `::` can shorten the address before them.
+fn compressed_prefix(literal: &str) -> Option<Prefix> {
+    let literal = literal.find("::").map_or(literal, |i| &literal[..=i]);
+    let (groups, _) = literal.rsplit_once(':')?;
+
+    let mut addr = [0u16; 8];
+    let mut len = 0;
+    for (group, slot) in groups.split(':').zip(addr.iter_mut()) {
+        // Only what `Ipv6Addr` prints: lowercase hex without leading zeros.
+        let valid = (1..=4).contains(&group.len())
+            && (group == "0" || !group.starts_with('0'))
+            && group
+                .bytes()
+                .all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'));
+        if !valid {
+            return None;
+        }
+        *slot = u16::from_str_radix(group, 16).ok()?;
+        len += 16;
+    }
+    Some(Prefix::new(Ipv6Addr::from(addr), len))
+}
+
+/// Every digit of a fixed-width literal like `0200:12` fixes 4 address bits.
+fn hex_prefix(literal: &str, expanded: bool) -> Option<Prefix> {
+    let mut addr = 0u128;
+    let mut len = 0u32;
+    for (i, b) in literal.bytes().enumerate() {
+        if expanded && i % 5 == 4 {
+            if b != b':' {
+                return None;
+            }
+            continue;
+        }
+        let digit = match b {
+            b'0'..=b'9' => b - b'0',
+            b'a'..=b'f' => b - b'a' + 10,
+            _ => return None,
+        };
+        if len == 128 {
+            return None;
+        }
+        addr |= (digit as u128) << (124 - len);
+        len += 4;
+    }
+    (len > 0).then(|| Prefix::new(Ipv6Addr::from(addr), len as u8))
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+struct Entry {
+    name: Option<String>,
+    #[serde(default)]
+    kind: Kind,
+    target: Option<MatchTarget>,
+    score: Option<Score>,
+    pattern: toml::Spanned<String>,
+    #[serde(default)]
+    min_height: u8,
+    output: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PatternFile {
+    pattern: Vec<Entry>,
+}
+
+/// Builds patterns from `-r` arguments.
+pub fn from_args(regexes: &[String], defaults: Defaults) -> Result<Vec<Pattern>, Error> {
+    regexes
+        .iter()
+        .enumerate()
+        .map(|(i, r)| from_arg(i, r, defaults))
+        .collect()
+}
+
+/// Builds pattern number `index` from a regex, such as one added while
+/// mining.
+pub fn from_arg(index: usize, regex: &str, defaults: Defaults) -> Result<Pattern, Error> {
+    new_pattern(
+        index,
+        None,
+        Kind::Regex,
+        defaults.target,
+        defaults.score,
+        regex,
+        0,
+        None,
+    )
+    .map_err(|e| Error::Pattern(format!("{:?}: {}", regex, e)))
+}
+
+/// Reads patterns from a TOML file if its name ends with `.toml`, or from a
+/// file with a regex per line otherwise.
+pub fn from_file(path: &Path, defaults: Defaults) -> Result<Vec<Pattern>, Error> {
+    let contents = std::fs::read_to_string(path).map_err(error::io(path.display()))?;
+    let is_toml = path.extension().map_or(false, |ext| ext == "toml");
+    let patterns = if is_toml {
+        parse_toml(&contents, defaults)
+    } else {
+        parse_lines(&contents, defaults)
+    }
+    .map_err(|e| Error::Pattern(format!("{}:{}", path.display(), e)))?;
+
+    if patterns.is_empty() {
+        return Err(Error::Pattern(format!("{}: no patterns", path.display())));
+    }
+    Ok(patterns)
+}
+
+/// One regex per line, skipping empty lines and `#` comments.
+fn parse_lines(contents: &str, defaults: Defaults) -> Result<Vec<Pattern>, String> {
+    let mut patterns = Vec::new();
+    for (n, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let pattern = new_pattern(
+            patterns.len(),
+            None,
+            Kind::Regex,
+            defaults.target,
+            defaults.score,
+            line,
+            0,
+            None,
+        )
+        .map_err(|e| format!("{}: {:?}: {}", n + 1, line, e))?;
+        patterns.push(pattern);
+    }
+    Ok(patterns)
+}
+
+fn parse_toml(contents: &str, defaults: Defaults) -> Result<Vec<Pattern>, String> {
+    let file: PatternFile = toml::from_str(contents).map_err(|e| {
+        let line = e.span().map_or(0, |span| line_of(contents, span.start));
+        format!("{}: {}", line, e.message())
+    })?;
+
+    file.pattern
+        .into_iter()
+        .enumerate()
+        .map(|(i, entry)| {
+            new_pattern(
+                i,
+                entry.name,
+                entry.kind,
+                entry.target.unwrap_or(defaults.target),
+                entry.score.unwrap_or(defaults.score),
+                entry.pattern.get_ref(),
+                entry.min_height,
+                entry.output,
+            )
+            .map_err(|e| {
+                format!(
+                    "{}: {:?}: {}",
+                    line_of(contents, entry.pattern.span().start),
+                    entry.pattern.get_ref(),
+                    e
+                )
+            })
+        })
+        .collect()
+}
+
+#[allow(clippy::too_many_arguments)]
+fn new_pattern(
+    index: usize,
+    name: Option<String>,
+    kind: Kind,
+    target: MatchTarget,
+    score: Score,
+    pattern: &str,
+    min_height: u8,
+    output: Option<String>,
+) -> Result<Pattern, String> {
+    let matcher = match kind {
+        Kind::Regex => Matcher::Regex(Regex::new(pattern).map_err(|e| e.to_string())?, target),
+        Kind::Prefix => Matcher::Prefix(Prefix::from_str(pattern)?),
+        Kind::Expr => Matcher::Expr(Expr::parse(pattern, target)?),
+    };
+    let output = output.unwrap_or_else(|| format!("pattern-{}", index));
+    if output.is_empty() || output.starts_with('.') || output.contains(['/', '\\']) {
+        return Err(format!("invalid output tag {:?}", output));
+    }
+    Ok(Pattern {
+        name: name.unwrap_or_else(|| pattern.to_owned()),
+        matcher,
+        min_height,
+        score,
+        output,
+    })
+}
+
+/// 1-based number of the line containing byte `offset`.
+fn line_of(contents: &str, offset: usize) -> usize {
+    contents[..offset].matches('\n').count() + 1
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::Ipv6Addr, str::FromStr};
+
+    use crate::score::Score;
+
+    use super::{
+        from_arg, from_args, literal_prefixes, parse_lines, parse_toml, Candidate, Defaults,
+        MatchTarget, PatternSet,
+    };
+
+    const COMPRESSED: MatchTarget = MatchTarget::Compressed;
+    const DEFAULTS: Defaults = Defaults {
+        target: COMPRESSED,
+        score: Score::Height,
+    };
+
+    #[test]
+    fn test_parse_lines() {
+        let patterns = parse_lines("# comment\n^200:\n\n1234$\n", DEFAULTS).unwrap();
+        assert_eq!(patterns.len(), 2);
+        assert_eq!(patterns[0].name, "^200:");
+        assert_eq!(patterns[1].output, "pattern-1");
+        assert!(matches!(
+            patterns[1].matcher,
+            super::Matcher::Regex(_, MatchTarget::Compressed)
+        ));
+
+        let err = parse_lines("^200:\n# comment\n(unclosed\n", DEFAULTS).unwrap_err();
+        assert!(err.starts_with("3: \"(unclosed\""), "{}", err);
+    }
+
+    #[test]
+    fn test_parse_toml() {
+        let patterns = parse_toml(
+            r#"
+[[pattern]]
+name = "office"
+kind = "prefix"
+pattern = "200:1234::/32"
+min-height = 10
+output = "office"
+score = "zero-run"
+
+[[pattern]]
+pattern = "beef"
+target = "expanded"
+
+[[pattern]]
+kind = "expr"
+pattern = 'prefix(200::/8) && !(height < 10)'
+"#,
+            DEFAULTS,
+        )
+        .unwrap();
+        assert_eq!(patterns.len(), 3);
+        assert_eq!(patterns[0].name, "office");
+        assert_eq!(patterns[0].min_height, 10);
+        assert_eq!(patterns[0].score, Score::ZeroRun);
+        let addr = Ipv6Addr::from_str("200:1234::1").unwrap();
+        assert!(patterns[0].is_match(&mut Candidate::new(&addr, &[0; 32], 0)));
+        let addr = Ipv6Addr::from_str("200:1235::1").unwrap();
+        assert!(!patterns[0].is_match(&mut Candidate::new(&addr, &[0; 32], 0)));
+        assert_eq!(patterns[1].name, "beef");
+        assert_eq!(patterns[1].output, "pattern-1");
+        assert!(matches!(
+            patterns[1].matcher,
+            super::Matcher::Regex(_, MatchTarget::Expanded)
+        ));
+        let addr = Ipv6Addr::from_str("20a::1").unwrap();
+        assert!(patterns[2].is_match(&mut Candidate::new(&addr, &[0; 32], 10)));
+        assert!(!patterns[2].is_match(&mut Candidate::new(&addr, &[0; 32], 9)));
+
+        let err = parse_toml(
+            r#"
+[[pattern]]
+kind = "prefix"
+pattern = "200:1234::"
+"#,
+            DEFAULTS,
+        )
+        .unwrap_err();
+        assert!(err.starts_with("4: "), "{}", err);
+
+        let err = parse_toml(
+            "[[pattern]]\npattern = \"a\"\noutput = \"../a\"\n",
+            DEFAULTS,
+        )
+        .unwrap_err();
+        assert!(err.contains("output tag"), "{}", err);
+    }
+
+    #[test]
+    fn test_literal_prefixes() {
+        let prefixes_for = |r, target| {
+            literal_prefixes(r, target).map(|p| p.iter().map(|p| p.to_string()).collect::<Vec<_>>())
+        };
+        let prefixes = |r| prefixes_for(r, COMPRESSED);
+        assert_eq!(
+            prefixes("^200:1234:5"),
+            Some(vec![String::from("200:1234::/32")])
+        );
+        assert_eq!(
+            prefixes("^20[01]:beef:"),
+            Some(vec![
+                String::from("200:beef::/32"),
+                String::from("201:beef::/32")
+            ])
+        );
+        assert_eq!(prefixes("^200:0:"), Some(vec![String::from("200::/32")]));
+        assert_eq!(prefixes("^200::1"), Some(vec![String::from("200::/16")]));
+        assert_eq!(prefixes("^200:0012:"), None);
+        assert_eq!(prefixes("^2"), None);
+        assert_eq!(prefixes("200:1234:"), None);
+        assert_eq!(prefixes(""), None);
+
+        assert_eq!(
+            prefixes_for("^0200:12", MatchTarget::Expanded),
+            Some(vec![String::from("200:1200::/24")])
+        );
+        assert_eq!(prefixes_for("^0200:012:", MatchTarget::Expanded), None);
+        assert_eq!(
+            prefixes_for("^02001", MatchTarget::Raw),
+            Some(vec![String::from("200:1000::/20")])
+        );
+        assert_eq!(prefixes_for("^0200", MatchTarget::PublicKey), None);
+    }
+
+    #[test]
+    fn test_pattern_set() {
+        let set = PatternSet::new(
+            from_args(
+                &[
+                    String::from("^200:1234:"),
+                    String::from("beef$"),
+                    String::from("^201:"),
+                ],
+                DEFAULTS,
+            )
+            .unwrap(),
+            Vec::new(),
+            Vec::new(),
+        )
+        .unwrap();
+        let addr = Ipv6Addr::from_str("200:1234::beef").unwrap();
+        assert!(set.may_match(&addr, 0));
+        assert_eq!(
+            set.matches(&mut Candidate::new(&addr, &[0; 32], 0)),
+            vec![0, 1]
+        );
+
+        let set = PatternSet::new(
+            from_args(
+                &[String::from("^200:1234:"), String::from("^201:")],
+                DEFAULTS,
+            )
+            .unwrap(),
+            Vec::new(),
+            Vec::new(),
+        )
+        .unwrap();
+        let addr = Ipv6Addr::from_str("200:1235::beef").unwrap();
+        assert!(!set.may_match(&addr, 0));
+        let addr = Ipv6Addr::from_str("201:1::").unwrap();
+        assert!(set.may_match(&addr, 1));
+        assert_eq!(
+            set.matches(&mut Candidate::new(&addr, &[0; 32], 0)),
+            vec![1]
+        );
+
+        let set = PatternSet::new(
+            from_args(&[String::from("^201:")], DEFAULTS).unwrap(),
+            from_args(&[String::from("^201:1:")], DEFAULTS).unwrap(),
+            Vec::new(),
+        )
+        .unwrap();
+        assert!(set
+            .matches(&mut Candidate::new(&addr, &[0; 32], 0))
+            .is_empty());
+        let addr = Ipv6Addr::from_str("201:2::").unwrap();
+        assert_eq!(
+            set.matches(&mut Candidate::new(&addr, &[0; 32], 0)),
+            vec![0]
+        );
+    }
+
+    #[test]
+    fn test_extend() {
+        let mut set = PatternSet::new(
+            from_args(&[String::from("^201:")], DEFAULTS).unwrap(),
+            Vec::new(),
+            Vec::new(),
+        )
+        .unwrap();
+        let addr = Ipv6Addr::from_str("202:1::beef").unwrap();
+        assert!(!set.may_match(&addr, 0));
+
+        set.extend(vec![from_arg(1, "beef$", DEFAULTS).unwrap()])
+            .unwrap();
+        assert_eq!(set.patterns[1].output, "pattern-1");
+        assert!(set.may_match(&addr, 0));
+        assert_eq!(
+            set.matches(&mut Candidate::new(&addr, &[0; 32], 0)),
+            vec![1]
+        );
+
+        set.extend(vec![from_arg(2, "^202:", DEFAULTS).unwrap()])
+            .unwrap();
+        assert_eq!(set.remove(0).unwrap().name, "^201:");
+        assert_eq!(
+            set.matches(&mut Candidate::new(&addr, &[0; 32], 0)),
+            vec![0, 1]
+        );
+        set.replace(1, from_arg(3, "^203:", DEFAULTS).unwrap())
+            .unwrap();
+        assert_eq!(
+            set.matches(&mut Candidate::new(&addr, &[0; 32], 0)),
+            vec![0]
+        );
+        assert_eq!(set.next_index(), 3);
+    }
+
+    #[test]
+    fn test_match_targets() {
+        let addr = Ipv6Addr::from_str("200:1234::1").unwrap();
+        let mut pk = [0u8; 32];
+        pk[31] = 0xab;
+        assert_eq!(MatchTarget::Compressed.text(&addr, &pk), "200:1234::1");
+        assert_eq!(
+            MatchTarget::Expanded.text(&addr, &pk),
+            "0200:1234:0000:0000:0000:0000:0000:0001"
+        );
+        assert_eq!(
+            MatchTarget::Raw.text(&addr, &pk),
+            "02001234000000000000000000000001"
+        );
+        assert!(MatchTarget::PublicKey.text(&addr, &pk).ends_with("00ab"));
+
+        let regexes = [String::from("^0200:1234:0000:"), String::from("0001$")];
+        let set = PatternSet::new(
+            from_args(
+                &regexes,
+                Defaults {
+                    target: MatchTarget::Expanded,
+                    ..DEFAULTS
+                },
+            )
+            .unwrap(),
+            Vec::new(),
+            Vec::new(),
+        )
+        .unwrap();
+        assert_eq!(set.matches(&mut Candidate::new(&addr, &pk, 0)), vec![0, 1]);
+    }
+}
+
+#[cfg(test)]
+mod benches {
+    extern crate test;
+
+    use std::net::Ipv6Addr;
+
+    use rand::{rngs::StdRng, RngCore, SeedableRng};
+    use test::Bencher;
+
+    use super::{from_args, Candidate, Defaults, PatternSet};
+    use crate::{handler::address_for_pubkey, PublicKey};
+
+    fn setup() -> (PatternSet, Vec<(Ipv6Addr, PublicKey)>) {
+        let regexes: Vec<String> = (0..256)
+            .map(|i| format!("^2{:02x}:{:x}:", i % 16, 0x1000 + i))
+            .collect();
+        let mut rng = StdRng::seed_from_u64(0);
+        let addrs = (0..1024)
+            .map(|_| {
+                let mut pk = [0u8; 32];
+                rng.fill_bytes(&mut pk);
+                (address_for_pubkey(&pk), pk)
+            })
+            .collect();
+        let patterns = from_args(&regexes, Defaults::default()).unwrap();
+        (
+            PatternSet::new(patterns, Vec::new(), Vec::new()).unwrap(),
+            addrs,
+        )
+    }
+
+    /// Formatting every address and trying patterns one by one.
+    #[bench]
+    fn bench_pattern_loop(b: &mut Bencher) {
+        let (set, addrs) = setup();
+        b.iter(|| {
+            let mut found = 0;
+            for (addr, pk) in &addrs {
+                let mut candidate = Candidate::new(addr, pk, 0);
+                for pattern in &set.patterns {
+                    if pattern.is_match(&mut candidate) {
+                        found += 1;
+                    }
+                }
+            }
+            found
+        });
+    }
+
+    #[bench]
+    fn bench_pattern_set(b: &mut Bencher) {
+        let (set, addrs) = setup();
+        b.iter(|| {
+            let mut found = 0;
+            for (addr, pk) in &addrs {
+                if set.may_match(addr, 0) {
+                    found += set.matches(&mut Candidate::new(addr, pk, 0)).len();
+                }
+            }
+            found
+        });
+    }
+}
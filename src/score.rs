@@ -0,0 +1,102 @@
+use std::path::Path;
+
+use serde::Deserialize;
+use strum_macros::{Display, EnumString};
+
+use crate::{
+    error::{self, Error},
+    patterns::{Candidate, MatchTarget},
+};
+
+/// Hex-spellable words counted by [`Score::Words`] unless `--words` is given.
+const DEFAULT_WORDS: &[&str] = &[
+    "abba", "abbe", "accede", "ace", "add", "babe", "bad", "bead", "beef", "cab", "cafe", "dab",
+    "dad", "dead", "deaf", "decade", "deed", "efface", "facade", "face", "fade", "fed", "fee",
+    "feed",
+];
+
+/// How results of a pattern are ranked.
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq, EnumString, Display)]
+#[serde(rename_all = "kebab-case")]
+#[strum(serialize_all = "kebab-case", ascii_case_insensitive)]
+pub enum Score {
+    /// Leading zero bits of the public key.
+    #[default]
+    Height,
+    /// Longest run of zero digits in the address.
+    ZeroRun,
+    /// Address digits equal to the digit before them.
+    Repeats,
+    /// Address groups that are zero.
+    ZeroGroups,
+    /// Dictionary words in the address as printed.
+    Words,
+}
+
+impl Score {
+    pub fn compute(self, candidate: &mut Candidate, words: &[String]) -> u32 {
+        let addr = *candidate.addr;
+        let nibbles = || addr.octets().into_iter().flat_map(|b| [b >> 4, b & 0xf]);
+        match self {
+            Self::Height => candidate.height as u32,
+            Self::ZeroRun => {
+                let mut run = 0;
+                let mut longest = 0;
+                for nibble in nibbles() {
+                    run = if nibble == 0 { run + 1 } else { 0 };
+                    longest = longest.max(run);
+                }
+                longest
+            }
+            Self::Repeats => {
+                let nibbles: Vec<_> = nibbles().collect();
+                nibbles.windows(2).filter(|w| w[0] == w[1]).count() as u32
+            }
+            Self::ZeroGroups => addr.segments().iter().filter(|g| **g == 0).count() as u32,
+            Self::Words => {
+                let text = candidate.text(MatchTarget::Compressed);
+                words
+                    .iter()
+                    .map(|w| text.matches(w.as_str()).count() as u32)
+                    .sum()
+            }
+        }
+    }
+}
+
+/// Reads a word per line from `path`, or returns the built-in list.
+pub fn load_words(path: Option<&Path>) -> Result<Vec<String>, Error> {
+    let Some(path) = path else {
+        return Ok(DEFAULT_WORDS.iter().map(|w| w.to_string()).collect());
+    };
+    let contents = std::fs::read_to_string(path).map_err(error::io(path.display()))?;
+    Ok(contents
+        .lines()
+        .map(|w| w.trim().to_ascii_lowercase())
+        .filter(|w| !w.is_empty())
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::Ipv6Addr, str::FromStr};
+
+    use super::Score;
+    use crate::patterns::Candidate;
+
+    fn score(score: Score, addr: &str) -> u32 {
+        let addr = Ipv6Addr::from_str(addr).unwrap();
+        let words = super::load_words(None).unwrap();
+        score.compute(&mut Candidate::new(&addr, &[0; 32], 7), &words)
+    }
+
+    #[test]
+    fn test_scores() {
+        assert_eq!(score(Score::Height, "207::"), 7);
+        assert_eq!(score(Score::ZeroRun, "207:1:2:3:4:5:6:7"), 3);
+        assert_eq!(score(Score::ZeroRun, "207:1::7"), 23);
+        assert_eq!(score(Score::Repeats, "2ff:1111:2:3:4:5:6:7"), 1 + 3 + 2 * 6);
+        assert_eq!(score(Score::ZeroGroups, "207:1::7"), 5);
+        assert_eq!(score(Score::Words, "207:cafe:beef::face"), 4);
+    }
+}
@@ -0,0 +1,125 @@
+//! Where the seeds a [`Miner`](crate::Miner) checks come from.
+
+use rand::RngCore;
+use rayon::prelude::*;
+use sha2::{Digest, Sha256};
+use zeroize::Zeroizing;
+
+use crate::{cpu::CHUNK_SIZE, error::Error, PublicKey, Seed};
+
+/// Secret the seeds of a distributed run are derived from.
+pub type MasterSecret = [u8; 32];
+
+/// Seed number `counter` under `secret`.
+pub fn derive(secret: &MasterSecret, counter: u64) -> Seed {
+    let mut hasher = Sha256::new();
+    hasher.update(secret);
+    hasher.update(counter.to_le_bytes());
+    hasher.finalize().into()
+}
+
+/// Counters `start..end` under a master secret.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WorkRange {
+    pub start: u64,
+    pub end: u64,
+}
+
+impl WorkRange {
+    pub fn len(&self) -> u64 {
+        self.end - self.start
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.start >= self.end
+    }
+}
+
+/// Supplies seeds to the mining thread, a batch at a time.
+pub trait SeedSource: Send {
+    /// Fills the start of `seeds`, returning how many were filled. Mining
+    /// ends once this returns zero.
+    fn fill(&mut self, seeds: &mut [Seed]) -> Result<usize, Error>;
+
+    /// Called with the public keys of every filled batch once it was
+    /// checked, in the order batches were filled.
+    fn checked(&mut self, _pubkeys: &[PublicKey]) -> Result<(), Error> {
+        Ok(())
+    }
+
+    /// Called whenever mining starts, including after the backend failed.
+    /// Batches filled before and not checked yet were lost.
+    fn start(&mut self) {}
+}
+
+/// Fresh random seeds, never running out. Each thread fills a chunk from
+/// its own RNG.
+pub struct RandomSeeds;
+
+impl SeedSource for RandomSeeds {
+    fn fill(&mut self, seeds: &mut [Seed]) -> Result<usize, Error> {
+        seeds
+            .par_chunks_mut(CHUNK_SIZE)
+            .for_each(|chunk| rand::thread_rng().fill_bytes(chunk.flatten_mut()));
+        Ok(seeds.len())
+    }
+}
+
+/// Seeds of a [`WorkRange`], in order.
+pub struct RangeSeeds {
+    secret: Zeroizing<MasterSecret>,
+    range: WorkRange,
+}
+
+impl RangeSeeds {
+    pub fn new(secret: Zeroizing<MasterSecret>, range: WorkRange) -> Self {
+        Self { secret, range }
+    }
+
+    /// Counters not handed out yet.
+    pub fn remaining(&self) -> WorkRange {
+        self.range
+    }
+
+    /// Hands out counters from `start` on again.
+    pub fn rewind(&mut self, start: u64) {
+        self.range.start = self.range.start.min(start);
+    }
+}
+
+impl SeedSource for RangeSeeds {
+    fn fill(&mut self, seeds: &mut [Seed]) -> Result<usize, Error> {
+        let count = self.range.len().min(seeds.len() as u64) as usize;
+        let (start, secret) = (self.range.start, &self.secret);
+        seeds[..count]
+            .par_iter_mut()
+            .enumerate()
+            .for_each(|(i, seed)| *seed = derive(secret, start + i as u64));
+        self.range.start += count as u64;
+        Ok(count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use zeroize::Zeroizing;
+
+    use super::{derive, RangeSeeds, SeedSource, WorkRange};
+
+    #[test]
+    fn test_range_seeds() {
+        let secret = [7u8; 32];
+        assert_ne!(derive(&secret, 0), derive(&secret, 1));
+        assert_ne!(derive(&secret, 0), derive(&[8u8; 32], 0));
+
+        let mut source = RangeSeeds::new(Zeroizing::new(secret), WorkRange { start: 10, end: 15 });
+        let mut seeds = [[0u8; 32]; 4];
+        assert_eq!(source.fill(&mut seeds).unwrap(), 4);
+        assert_eq!(seeds[0], derive(&secret, 10));
+        assert_eq!(seeds[3], derive(&secret, 13));
+        assert_eq!(source.fill(&mut seeds).unwrap(), 1);
+        assert_eq!(seeds[0], derive(&secret, 14));
+        assert_eq!(source.fill(&mut seeds).unwrap(), 0);
+        assert!(source.remaining().is_empty());
+    }
+}
@@ -0,0 +1,448 @@
+use std::{
+    collections::VecDeque,
+    fmt,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicU32, AtomicU64, AtomicU8, Ordering},
+        Arc, Mutex, RwLock,
+    },
+    time::{Duration, Instant},
+};
+
+use crate::estimate::{self, Target};
+
+/// Number of samples the moving average is computed over.
+const WINDOW: usize = 12;
+
+/// Counters of a single device taking part in mining.
+pub struct DeviceStats {
+    pub name: String,
+    pub keys: AtomicU64,
+    pub batches: AtomicU64,
+    pub batch_nanos: AtomicU64,
+}
+
+impl DeviceStats {
+    /// Records a finished batch of `keys` keys that took `elapsed`.
+    pub fn add_batch(&self, keys: u64, elapsed: Duration) {
+        self.keys.fetch_add(keys, Ordering::Relaxed);
+        self.batches.fetch_add(1, Ordering::Relaxed);
+        self.batch_nanos
+            .fetch_add(elapsed.as_nanos() as u64, Ordering::Relaxed);
+    }
+}
+
+/// Which matches are reported.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Report {
+    /// Every match.
+    All,
+    /// Matches scoring at least as high as the best one so far.
+    Best,
+    /// Matches of at least this height.
+    AtLeast(u8),
+    /// Matches among the best this many of their pattern.
+    Top(usize),
+}
+
+impl Report {
+    /// Leaderboard size per pattern, if matches are ranked.
+    pub fn top(self) -> Option<usize> {
+        match self {
+            Self::All | Self::AtLeast(_) => None,
+            Self::Best => Some(1),
+            Self::Top(top) => Some(top),
+        }
+    }
+}
+
+impl FromStr for Report {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, value) = match s.split_once(':') {
+            Some((name, value)) => (name, Some(value)),
+            None => (s, None),
+        };
+        match (name, value) {
+            ("all", None) => Ok(Self::All),
+            ("best", None) => Ok(Self::Best),
+            ("at-least", Some(height)) => height
+                .parse()
+                .map(Self::AtLeast)
+                .map_err(|e| format!("bad height {:?}: {}", height, e)),
+            ("top", Some(top)) => match top.parse() {
+                Ok(0) => Err(String::from("top needs at least one result")),
+                Ok(top) => Ok(Self::Top(top)),
+                Err(e) => Err(format!("bad count {:?}: {}", top, e)),
+            },
+            _ => Err(format!(
+                "unknown report policy {:?}, expected all, best, at-least:N or top:K",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::All => write!(f, "all"),
+            Self::Best => write!(f, "best"),
+            Self::AtLeast(height) => write!(f, "at-least:{}", height),
+            Self::Top(top) => write!(f, "top:{}", top),
+        }
+    }
+}
+
+/// Per-pattern results.
+pub struct PatternStats {
+    pub pattern: String,
+    pub found: AtomicU64,
+    pub best_height: AtomicU8,
+    /// When the last result was reported.
+    last_found: Mutex<Option<Instant>>,
+    /// Best scores reported, highest first.
+    leaderboard: Mutex<Vec<u32>>,
+    /// Lowest score that can still enter the leaderboard.
+    threshold: AtomicU32,
+}
+
+impl PatternStats {
+    fn new(pattern: String) -> Self {
+        Self {
+            pattern,
+            found: AtomicU64::new(0),
+            best_height: AtomicU8::new(0),
+            last_found: Mutex::new(None),
+            leaderboard: Mutex::new(Vec::new()),
+            threshold: AtomicU32::new(0),
+        }
+    }
+
+    /// Time since the last result was reported, if any was.
+    pub fn since_found(&self) -> Option<Duration> {
+        self.last_found.lock().unwrap().map(|t| t.elapsed())
+    }
+}
+
+/// Shared counters of a mining run.
+pub struct Stats {
+    start: Instant,
+    pub backend: String,
+    devices: Mutex<Vec<Arc<DeviceStats>>>,
+    /// Indexed like the patterns of the miner, growing as patterns are added.
+    patterns: RwLock<Vec<Arc<PatternStats>>>,
+    pub verification_failures: AtomicU64,
+    /// Size of per-pattern leaderboards, if matches are ranked.
+    top: Option<usize>,
+    /// Lowest leaderboard threshold over all patterns.
+    lowest_threshold: AtomicU32,
+    /// Moving-average hashrate as `f64` bits, updated by the reporter.
+    hashrate: AtomicU64,
+}
+
+impl Stats {
+    pub fn new(
+        backend: impl Into<String>,
+        patterns: impl IntoIterator<Item = String>,
+        top: Option<usize>,
+    ) -> Self {
+        Self {
+            start: Instant::now(),
+            backend: backend.into(),
+            devices: Mutex::new(Vec::new()),
+            patterns: RwLock::new(
+                patterns
+                    .into_iter()
+                    .map(|pattern| Arc::new(PatternStats::new(pattern)))
+                    .collect(),
+            ),
+            verification_failures: AtomicU64::new(0),
+            top,
+            lowest_threshold: AtomicU32::new(0),
+            hashrate: AtomicU64::new(0f64.to_bits()),
+        }
+    }
+
+    /// Registers a device and returns the counters it should report batches to.
+    pub fn register(&self, name: impl Into<String>) -> Arc<DeviceStats> {
+        let device = Arc::new(DeviceStats {
+            name: name.into(),
+            keys: AtomicU64::new(0),
+            batches: AtomicU64::new(0),
+            batch_nanos: AtomicU64::new(0),
+        });
+        self.devices.lock().unwrap().push(device.clone());
+        device
+    }
+
+    /// Adds counters for a pattern added while mining.
+    pub fn add_pattern(&self, pattern: impl Into<String>) {
+        let mut patterns = self.patterns.write().unwrap();
+        patterns.push(Arc::new(PatternStats::new(pattern.into())));
+        // Anything can enter the leaderboard of the new pattern.
+        self.lowest_threshold.store(0, Ordering::Relaxed);
+    }
+
+    /// Starts counting pattern number `index` over, for a pattern put in
+    /// place of the old one.
+    pub fn replace_pattern(&self, index: usize, pattern: impl Into<String>) {
+        let mut patterns = self.patterns.write().unwrap();
+        patterns[index] = Arc::new(PatternStats::new(pattern.into()));
+        self.lowest_threshold.store(0, Ordering::Relaxed);
+    }
+
+    /// Drops the counters of pattern number `index`, moving the ones after
+    /// it down.
+    pub fn remove_pattern(&self, index: usize) {
+        let mut patterns = self.patterns.write().unwrap();
+        patterns.remove(index);
+        let lowest = patterns
+            .iter()
+            .map(|p| p.threshold.load(Ordering::Relaxed))
+            .min()
+            .unwrap_or(0);
+        self.lowest_threshold.store(lowest, Ordering::Relaxed);
+    }
+
+    pub fn patterns(&self) -> Vec<Arc<PatternStats>> {
+        self.patterns.read().unwrap().clone()
+    }
+
+    /// Scores below this can't enter any leaderboard.
+    pub fn lowest_threshold(&self) -> u32 {
+        self.lowest_threshold.load(Ordering::Relaxed)
+    }
+
+    /// Cheap check whether a match of pattern number `pattern` with `score`
+    /// can be reported.
+    pub fn may_report(&self, pattern: usize, score: u32) -> bool {
+        let patterns = self.patterns.read().unwrap();
+        score >= patterns[pattern].threshold.load(Ordering::Relaxed)
+    }
+
+    /// Records a verified match of pattern number `pattern`, returning
+    /// whether to report it along with its 1-based rank if matches are
+    /// ranked. Reported matches are counted.
+    pub fn report(&self, pattern: usize, score: u32, height: u8) -> Option<Option<usize>> {
+        let rank = match self.top {
+            Some(top) => Some(self.rank(pattern, score, top)?),
+            None => None,
+        };
+        let patterns = self.patterns.read().unwrap();
+        let p = &patterns[pattern];
+        p.best_height.fetch_max(height, Ordering::Relaxed);
+        p.found.fetch_add(1, Ordering::Relaxed);
+        *p.last_found.lock().unwrap() = Some(Instant::now());
+        Some(rank)
+    }
+
+    /// Puts a result with `score` on the leaderboard of pattern number
+    /// `pattern` if it's at least as good as the worst of the best `top`
+    /// results, returning its 1-based rank.
+    fn rank(&self, pattern: usize, score: u32, top: usize) -> Option<usize> {
+        let patterns = self.patterns.read().unwrap();
+        let p = &patterns[pattern];
+        let mut leaderboard = p.leaderboard.lock().unwrap();
+        if leaderboard.len() == top && leaderboard.last() > Some(&score) {
+            return None;
+        }
+        // Ties push out older results.
+        let rank = leaderboard.partition_point(|s| *s > score);
+        leaderboard.insert(rank, score);
+        leaderboard.truncate(top);
+        if leaderboard.len() == top {
+            p.threshold.store(leaderboard[top - 1], Ordering::Relaxed);
+        }
+        drop(leaderboard);
+
+        // Still holding `patterns`, so an added pattern can't be missed.
+        let lowest = patterns
+            .iter()
+            .map(|p| p.threshold.load(Ordering::Relaxed))
+            .min()
+            .unwrap_or(0);
+        self.lowest_threshold.fetch_max(lowest, Ordering::Relaxed);
+        Some(rank + 1)
+    }
+
+    /// Time since mining started.
+    pub fn uptime(&self) -> Duration {
+        self.start.elapsed()
+    }
+
+    /// Keys generated by all devices.
+    pub fn keys(&self) -> u64 {
+        self.devices
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|d| d.keys.load(Ordering::Relaxed))
+            .sum()
+    }
+
+    /// Results reported for all patterns.
+    pub fn found(&self) -> u64 {
+        self.patterns
+            .read()
+            .unwrap()
+            .iter()
+            .map(|p| p.found.load(Ordering::Relaxed))
+            .sum()
+    }
+
+    pub fn devices(&self) -> Vec<Arc<DeviceStats>> {
+        self.devices.lock().unwrap().clone()
+    }
+
+    /// Moving-average hashrate in keys per second.
+    pub fn hashrate(&self) -> f64 {
+        f64::from_bits(self.hashrate.load(Ordering::Relaxed))
+    }
+
+    fn snapshot(&self) -> Vec<(String, u64)> {
+        self.devices()
+            .iter()
+            .map(|d| (d.name.clone(), d.keys.load(Ordering::Relaxed)))
+            .collect()
+    }
+}
+
+/// Spawns a thread sampling hashrate every `interval`. If `print` is set,
+/// stats are printed too, with an ETA for `target` if one is set.
+pub fn spawn_reporter(stats: Arc<Stats>, interval: Duration, target: Target, print: bool) {
+    let probability = target.is_set().then(|| target.probability());
+
+    std::thread::spawn(move || {
+        let mut last_time = Instant::now();
+        let mut last = stats.snapshot();
+        let mut window: VecDeque<(Duration, u64)> = VecDeque::with_capacity(WINDOW);
+
+        loop {
+            std::thread::sleep(interval);
+
+            let now = Instant::now();
+            let current = stats.snapshot();
+            let dt = now - last_time;
+
+            let deltas: Vec<u64> = current
+                .iter()
+                .enumerate()
+                .map(|(i, (_, keys))| keys - last.get(i).map_or(0, |(_, k)| *k))
+                .collect();
+            let total: u64 = current.iter().map(|(_, keys)| keys).sum();
+            let delta: u64 = deltas.iter().sum();
+
+            if window.len() == WINDOW {
+                window.pop_front();
+            }
+            window.push_back((dt, delta));
+            let window_time: f64 = window.iter().map(|(t, _)| t.as_secs_f64()).sum();
+            let window_keys: u64 = window.iter().map(|(_, k)| k).sum();
+            let avg_rate = window_keys as f64 / window_time;
+            stats.hashrate.store(avg_rate.to_bits(), Ordering::Relaxed);
+
+            last_time = now;
+            if !print {
+                last = current;
+                continue;
+            }
+
+            let mut line = format!(
+                "[{}] Hashrate: {} (avg {}), total: {:.2}M keys",
+                format_elapsed(now - stats.start),
+                format_rate(delta as f64 / dt.as_secs_f64()),
+                format_rate(avg_rate),
+                total as f64 / 1_000_000.0,
+            );
+            for ((name, _), delta) in current.iter().zip(deltas) {
+                let rate = delta as f64 / dt.as_secs_f64();
+                line.push_str(&format!(" | {}: {}", name, format_rate(rate)));
+            }
+            if let Some(probability) = probability {
+                line.push_str(&format!(
+                    " | ETA: {}",
+                    estimate::format_eta(probability, avg_rate)
+                ));
+            }
+            println!("{}", line);
+
+            last = current;
+        }
+    });
+}
+
+/// `keys_per_sec` in MH/s, as stats output shows it.
+pub fn format_rate(keys_per_sec: f64) -> String {
+    format!("{:.3} MH/s", keys_per_sec / 1_000_000.0)
+}
+
+fn format_elapsed(elapsed: Duration) -> String {
+    let secs = elapsed.as_secs();
+    format!("{:02}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use super::{Report, Stats};
+
+    fn stats(report: Report) -> Stats {
+        Stats::new("CPU", [String::from("a"), String::from("b")], report.top())
+    }
+
+    #[test]
+    fn test_report_top() {
+        let stats = stats(Report::Top(2));
+        assert_eq!(stats.report(0, 5, 0), Some(Some(1)));
+        assert_eq!(stats.report(0, 7, 0), Some(Some(1)));
+        assert_eq!(stats.lowest_threshold(), 0);
+        assert_eq!(stats.report(0, 6, 0), Some(Some(2)));
+        assert!(!stats.may_report(0, 5));
+        assert_eq!(stats.report(0, 5, 0), None);
+        assert_eq!(stats.report(0, 6, 0), Some(Some(2)));
+        assert_eq!(stats.lowest_threshold(), 0);
+        stats.report(1, 3, 0);
+        stats.report(1, 4, 0);
+        assert_eq!(stats.lowest_threshold(), 3);
+        assert_eq!(stats.found(), 6);
+
+        stats.add_pattern("c");
+        assert_eq!(stats.lowest_threshold(), 0);
+        assert!(stats.patterns()[2].since_found().is_none());
+        assert_eq!(stats.report(2, 1, 0), Some(Some(1)));
+        assert!(stats.patterns()[2].since_found().is_some());
+
+        stats.remove_pattern(2);
+        assert_eq!(stats.lowest_threshold(), 3);
+        stats.replace_pattern(1, "d");
+        assert_eq!(stats.lowest_threshold(), 0);
+        assert_eq!(stats.patterns()[1].pattern, "d");
+        assert_eq!(stats.found(), 4);
+    }
+
+    #[test]
+    fn test_report_best_and_all() {
+        let stats = stats(Report::Best);
+        assert_eq!(stats.report(0, 12, 12), Some(Some(1)));
+        assert_eq!(stats.report(0, 11, 11), None);
+        assert_eq!(stats.report(0, 12, 12), Some(Some(1)));
+
+        let stats = stats(Report::All);
+        assert_eq!(stats.report(0, 12, 12), Some(None));
+        assert_eq!(stats.report(0, 11, 11), Some(None));
+        assert!(stats.may_report(0, 0));
+    }
+
+    #[test]
+    fn test_parse_report() {
+        for s in ["all", "best", "at-least:10", "top:5"] {
+            assert_eq!(Report::from_str(s).unwrap().to_string(), s);
+        }
+        assert!(Report::from_str("top:0").is_err());
+        assert!(Report::from_str("at-least").is_err());
+        assert!(Report::from_str("best:3").is_err());
+    }
+}
@@ -0,0 +1,195 @@
+//! Async access to a running [`Miner`], see [`Miner::stream`].
+
+use std::{
+    net::Ipv6Addr,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    task::{Context, Poll},
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+use futures::{
+    channel::mpsc::{UnboundedReceiver, UnboundedSender},
+    Stream,
+};
+use zeroize::Zeroizing;
+
+use crate::{
+    error::Error,
+    miner::{Hit, Miner, ResultSink},
+    stats::Stats,
+    PublicKey, Seed,
+};
+
+/// Shortest time between stats events.
+pub const MIN_STATS_INTERVAL: Duration = Duration::from_millis(100);
+
+/// An owned copy of a [`Hit`].
+pub struct Found {
+    pub seed: Zeroizing<Seed>,
+    pub public_key: PublicKey,
+    pub address: Ipv6Addr,
+    pub height: u8,
+    /// Name of the matched pattern.
+    pub pattern: String,
+    pub score: u32,
+    pub rank: Option<usize>,
+}
+
+impl From<&Hit<'_>> for Found {
+    fn from(hit: &Hit) -> Self {
+        Self {
+            seed: Zeroizing::new(*hit.seed),
+            public_key: *hit.public_key,
+            address: hit.address,
+            height: hit.height,
+            pattern: hit.pattern.name.clone(),
+            score: hit.score,
+            rank: hit.rank,
+        }
+    }
+}
+
+/// Counters sampled while mining.
+#[derive(Debug, Clone, Copy)]
+pub struct Progress {
+    pub uptime: Duration,
+    pub keys: u64,
+    pub found: u64,
+    /// Keys per second since the previous sample.
+    pub hashrate: f64,
+}
+
+pub enum Event {
+    Hit(Found),
+    Stats(Progress),
+    /// Mining failed; the stream ends after this.
+    Error(Error),
+}
+
+/// Forwards hits to a [`MinerStream`].
+pub(crate) struct ChannelSink(pub UnboundedSender<Event>);
+
+impl ResultSink for ChannelSink {
+    fn found(&self, hit: &Hit) -> Result<(), Error> {
+        self.0
+            .unbounded_send(Event::Hit(hit.into()))
+            .map_err(|e| Error::Sink(e.to_string()))
+    }
+}
+
+/// Hits and stats of a miner running on its own thread. Ends when the
+/// miner stops on its own, dropping it stops the miner.
+///
+/// Every hit is kept until read, while stats taken before the previous
+/// ones were read are merged into the next.
+pub struct MinerStream {
+    miner: Arc<Miner>,
+    receiver: UnboundedReceiver<Event>,
+    /// Set while a stats event waits to be read.
+    stats_pending: Arc<AtomicBool>,
+}
+
+impl MinerStream {
+    pub(crate) fn spawn(
+        miner: Arc<Miner>,
+        sender: UnboundedSender<Event>,
+        receiver: UnboundedReceiver<Event>,
+        stats_interval: Duration,
+    ) -> Self {
+        let stats_pending = Arc::new(AtomicBool::new(false));
+        {
+            let miner = miner.clone();
+            let pending = stats_pending.clone();
+            std::thread::spawn(move || {
+                let ticker = spawn_ticker(miner.clone(), sender.clone(), pending, stats_interval);
+                let result = miner.run();
+                ticker.join().unwrap();
+                if let Err(e) = result {
+                    let _ = sender.unbounded_send(Event::Error(e));
+                }
+                sender.close_channel();
+            });
+        }
+        Self {
+            miner,
+            receiver,
+            stats_pending,
+        }
+    }
+
+    pub fn stats(&self) -> &Arc<Stats> {
+        self.miner.stats()
+    }
+
+    /// Stops the miner; the stream ends once its thread is done, after the
+    /// device was released and seed buffers were wiped.
+    pub fn stop(&self) {
+        self.miner.stop();
+    }
+}
+
+impl Stream for MinerStream {
+    type Item = Event;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Event>> {
+        let event = Pin::new(&mut self.receiver).poll_next(cx);
+        if let Poll::Ready(Some(Event::Stats(_))) = event {
+            self.stats_pending.store(false, Ordering::Relaxed);
+        }
+        event
+    }
+}
+
+impl Drop for MinerStream {
+    /// Stops the miner without waiting for it, so that dropping doesn't
+    /// block an async runtime. Its thread releases the device and wipes
+    /// seed buffers once the current batch is done; to wait for that, call
+    /// [`MinerStream::stop`] and read the stream to its end instead.
+    fn drop(&mut self) {
+        self.miner.stop();
+    }
+}
+
+/// Sends stats every `interval`, at least [`MIN_STATS_INTERVAL`], and once
+/// more when the miner stops. Stats aren't sent while earlier ones are
+/// `pending`; the next cover the time since the last sent.
+fn spawn_ticker(
+    miner: Arc<Miner>,
+    sender: UnboundedSender<Event>,
+    pending: Arc<AtomicBool>,
+    interval: Duration,
+) -> JoinHandle<()> {
+    let interval = interval.max(MIN_STATS_INTERVAL);
+    std::thread::spawn(move || {
+        let stats = miner.stats();
+        let mut last = (Instant::now(), stats.keys());
+        loop {
+            // Sleep in short steps to notice stopping quickly.
+            let deadline = Instant::now() + interval;
+            while Instant::now() < deadline && !miner.is_stopping() {
+                std::thread::sleep(MIN_STATS_INTERVAL);
+            }
+            if pending.load(Ordering::Relaxed) && !miner.is_stopping() {
+                continue;
+            }
+
+            let now = (Instant::now(), stats.keys());
+            let progress = Progress {
+                uptime: stats.uptime(),
+                keys: now.1,
+                found: stats.found(),
+                hashrate: (now.1 - last.1) as f64 / (now.0 - last.0).as_secs_f64(),
+            };
+            last = now;
+            pending.store(true, Ordering::Relaxed);
+            if sender.unbounded_send(Event::Stats(progress)).is_err() || miner.is_stopping() {
+                break;
+            }
+        }
+    })
+}
@@ -0,0 +1,469 @@
+//! `--tui` dashboard: hashrate graphs per device, results per pattern and a
+//! panel of found addresses, with keys to pause mining and add patterns.
+
+use std::{
+    collections::VecDeque,
+    io::{self, Stdout},
+    net::Ipv6Addr,
+    sync::{atomic::Ordering, Arc, Mutex},
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+use crossterm::{
+    event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers},
+    execute,
+    terminal::{self, EnterAlternateScreen, LeaveAlternateScreen},
+};
+use hex::ToHex;
+use ratatui::{
+    backend::{Backend, CrosstermBackend},
+    layout::{Constraint, Direction, Layout, Rect},
+    style::{Color, Modifier, Style},
+    widgets::{Block, Borders, List, ListItem, ListState, Paragraph, Row, Sparkline, Table},
+    Frame, Terminal,
+};
+use ygglkan::{
+    estimate::{self, Target},
+    patterns,
+    stats::format_rate,
+    Error, Hit, Miner, ResultSink,
+};
+use zeroize::Zeroizing;
+
+/// Time between hashrate samples of the graphs.
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Samples kept per device, more than fit on a wide terminal.
+const HISTORY: usize = 512;
+
+/// Longest wait for a key press between redraws.
+const FRAME: Duration = Duration::from_millis(250);
+
+/// Graphs stacked before devices share a row.
+const GRAPH_ROWS: usize = 4;
+
+/// A hit shown in the results panel.
+struct Found {
+    at: Instant,
+    address: Ipv6Addr,
+    height: u8,
+    pattern: String,
+    private_key: Zeroizing<[u8; 64]>,
+}
+
+/// Hits kept for the results panel, oldest first.
+#[derive(Default)]
+pub struct Results(Mutex<Vec<Found>>);
+
+/// Passes hits on to `sink`, keeping the ones it took for the results panel.
+pub fn sink(
+    results: Arc<Results>,
+    sink: impl ResultSink + 'static,
+) -> impl Fn(&Hit) -> Result<(), Error> + Send + Sync {
+    move |hit: &Hit| {
+        sink.found(hit)?;
+        results.0.lock().unwrap().push(Found {
+            at: Instant::now(),
+            address: hit.address,
+            height: hit.height,
+            pattern: hit.pattern.name.clone(),
+            private_key: hit.private_key(),
+        });
+        Ok(())
+    }
+}
+
+/// What typed keys go to.
+enum Mode {
+    Normal,
+    /// Typing the regex of a pattern to add.
+    AddPattern(String),
+}
+
+pub struct Dashboard {
+    miner: Arc<Miner>,
+    results: Arc<Results>,
+    defaults: patterns::Defaults,
+    /// `--target` and the probability of a key meeting it, if set.
+    target: Option<(String, f64)>,
+    /// Hashrate samples of every device, in keys per second.
+    history: Vec<VecDeque<u64>>,
+    /// When devices were sampled last and their key counts then.
+    sampled: (Instant, Vec<u64>),
+    /// Selection in the results panel, newest first.
+    selected: ListState,
+    /// Results in the panel when it was drawn last.
+    shown: usize,
+    /// Result whose key is shown, oldest first.
+    revealed: Option<usize>,
+    mode: Mode,
+    status: String,
+}
+
+impl Dashboard {
+    pub fn new(
+        miner: Arc<Miner>,
+        results: Arc<Results>,
+        defaults: patterns::Defaults,
+        target: &Target,
+    ) -> Self {
+        Self {
+            miner,
+            results,
+            defaults,
+            target: target
+                .is_set()
+                .then(|| (target.to_string(), target.probability())),
+            history: Vec::new(),
+            sampled: (Instant::now(), Vec::new()),
+            selected: ListState::default(),
+            shown: 0,
+            revealed: None,
+            mode: Mode::Normal,
+            status: String::new(),
+        }
+    }
+
+    /// Shows the dashboard until `mining` returns. Quitting stops the miner
+    /// first and exits right away when asked again.
+    pub fn run(mut self, mining: &JoinHandle<Result<(), Error>>) -> Result<(), Error> {
+        let mut screen = Screen::new().map_err(terminal_error)?;
+        while !mining.is_finished() {
+            self.sample();
+            screen.0.draw(|f| self.draw(f)).map_err(terminal_error)?;
+            if !event::poll(FRAME).map_err(terminal_error)? {
+                continue;
+            }
+            match event::read().map_err(terminal_error)? {
+                Event::Key(key) if key.kind == KeyEventKind::Press => {
+                    if self.key(key) {
+                        drop(screen);
+                        std::process::exit(130);
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Handles a key press, returning whether to exit right away.
+    fn key(&mut self, key: KeyEvent) -> bool {
+        if let Mode::AddPattern(input) = &mut self.mode {
+            match key.code {
+                KeyCode::Enter => {
+                    let regex = std::mem::take(input);
+                    self.mode = Mode::Normal;
+                    self.add_pattern(&regex);
+                }
+                KeyCode::Esc => self.mode = Mode::Normal,
+                KeyCode::Backspace => {
+                    input.pop();
+                }
+                KeyCode::Char(c) => input.push(c),
+                _ => {}
+            }
+            return false;
+        }
+
+        match key.code {
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                return self.quit()
+            }
+            KeyCode::Char('q') | KeyCode::Esc => return self.quit(),
+            KeyCode::Char('p') if self.miner.is_paused() => {
+                self.miner.resume();
+                self.status = String::from("Resumed");
+            }
+            KeyCode::Char('p') => {
+                self.miner.pause();
+                self.status = String::from("Paused, press p to resume");
+            }
+            KeyCode::Char('a') => self.mode = Mode::AddPattern(String::new()),
+            KeyCode::Up | KeyCode::Char('k') => self.select(-1),
+            KeyCode::Down | KeyCode::Char('j') => self.select(1),
+            KeyCode::Enter => {
+                let index = self.selected.selected().map(|i| self.shown - 1 - i);
+                self.revealed = match self.revealed == index {
+                    true => None,
+                    false => index,
+                };
+            }
+            _ => {}
+        }
+        false
+    }
+
+    fn quit(&mut self) -> bool {
+        if self.miner.is_stopping() {
+            return true;
+        }
+        self.miner.stop();
+        self.status = String::from("Stopping, press q again to exit immediately...");
+        false
+    }
+
+    fn add_pattern(&mut self, regex: &str) {
+        let pattern = patterns::from_arg(self.miner.next_pattern_index(), regex, self.defaults);
+        self.status = match pattern.and_then(|p| self.miner.add_patterns(vec![p])) {
+            Ok(()) => format!("Added pattern {}", regex),
+            Err(e) => e.to_string(),
+        };
+    }
+
+    fn select(&mut self, step: isize) {
+        if self.shown == 0 {
+            return;
+        }
+        let selected = match self.selected.selected() {
+            Some(i) => (i as isize + step).clamp(0, self.shown as isize - 1) as usize,
+            None => 0,
+        };
+        self.selected.select(Some(selected));
+    }
+
+    /// Adds a hashrate sample of every device once per [`SAMPLE_INTERVAL`].
+    fn sample(&mut self) {
+        let elapsed = self.sampled.0.elapsed();
+        if elapsed < SAMPLE_INTERVAL {
+            return;
+        }
+        let keys: Vec<u64> = self
+            .miner
+            .stats()
+            .devices()
+            .iter()
+            .map(|d| d.keys.load(Ordering::Relaxed))
+            .collect();
+        self.history.resize_with(keys.len(), VecDeque::new);
+        for (i, (history, now)) in self.history.iter_mut().zip(&keys).enumerate() {
+            let before = self.sampled.1.get(i).copied().unwrap_or(0);
+            if history.len() == HISTORY {
+                history.pop_front();
+            }
+            history.push_back(((now - before) as f64 / elapsed.as_secs_f64()) as u64);
+        }
+        self.sampled = (Instant::now(), keys);
+    }
+
+    fn draw<B: Backend>(&mut self, f: &mut Frame<B>) {
+        let patterns = self.miner.stats().patterns().len() as u16;
+        let graphs = self.history.len().clamp(1, GRAPH_ROWS) as u16;
+        let chunks = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints(
+                [
+                    Constraint::Length(4),
+                    Constraint::Length(graphs * 3),
+                    Constraint::Length(patterns.min(8) + 3),
+                    Constraint::Min(5),
+                    Constraint::Length(1),
+                ]
+                .as_ref(),
+            )
+            .split(f.size());
+        self.draw_summary(f, chunks[0]);
+        self.draw_graphs(f, chunks[1]);
+        self.draw_patterns(f, chunks[2]);
+        self.draw_results(f, chunks[3]);
+        self.draw_footer(f, chunks[4]);
+    }
+
+    fn draw_summary<B: Backend>(&self, f: &mut Frame<B>, area: Rect) {
+        let stats = self.miner.stats();
+        let state = if self.miner.is_stopping() {
+            "stopping"
+        } else if self.miner.is_paused() {
+            "paused"
+        } else {
+            "mining"
+        };
+        let next = stats
+            .patterns()
+            .iter()
+            .map(|p| p.best_height.load(Ordering::Relaxed))
+            .max()
+            .unwrap_or(0)
+            .saturating_add(1);
+        let target = Target {
+            height: next,
+            prefix: None,
+        };
+        let mut eta = format!(
+            "Height {}: {}",
+            next,
+            estimate::format_eta(target.probability(), stats.hashrate())
+        );
+        if let Some((target, probability)) = &self.target {
+            eta.push_str(&format!(
+                " | {}: {}",
+                target,
+                estimate::format_eta(*probability, stats.hashrate())
+            ));
+        }
+        let text = format!(
+            "{} {}, up {} | {} | {:.2}M keys | {} found\n{}",
+            stats.backend,
+            state,
+            estimate::format_duration(stats.uptime().as_secs_f64()),
+            format_rate(stats.hashrate()),
+            stats.keys() as f64 / 1_000_000.0,
+            stats.found(),
+            eta
+        );
+        let block = Block::default().borders(Borders::ALL).title("ygglkan");
+        f.render_widget(Paragraph::new(text).block(block), area);
+    }
+
+    fn draw_graphs<B: Backend>(&self, f: &mut Frame<B>, area: Rect) {
+        let devices = self.miner.stats().devices();
+        let rows = devices.len().clamp(1, GRAPH_ROWS);
+        let columns = (devices.len() + rows - 1) / rows;
+        let rows = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints(vec![Constraint::Ratio(1, rows as u32); rows])
+            .split(area);
+        for ((i, device), history) in devices.iter().enumerate().zip(&self.history) {
+            let cells = Layout::default()
+                .direction(Direction::Horizontal)
+                .constraints(vec![Constraint::Ratio(1, columns as u32); columns])
+                .split(rows[i / columns]);
+            let cell = cells[i % columns];
+            // The graph shows as many of the latest samples as fit.
+            let width = cell.width.saturating_sub(2) as usize;
+            let samples: Vec<u64> = history
+                .iter()
+                .skip(history.len().saturating_sub(width))
+                .copied()
+                .collect();
+            let title = format!(
+                "{}: {}",
+                device.name,
+                format_rate(samples.last().copied().unwrap_or(0) as f64)
+            );
+            let graph = Sparkline::default()
+                .block(Block::default().borders(Borders::ALL).title(title))
+                .style(Style::default().fg(Color::Green))
+                .data(&samples);
+            f.render_widget(graph, cell);
+        }
+    }
+
+    fn draw_patterns<B: Backend>(&self, f: &mut Frame<B>, area: Rect) {
+        let rows = self.miner.stats().patterns().into_iter().map(|p| {
+            let last = match p.since_found() {
+                Some(since) => format!("{} ago", estimate::format_duration(since.as_secs_f64())),
+                None => String::from("never"),
+            };
+            Row::new(vec![
+                p.pattern.clone(),
+                p.found.load(Ordering::Relaxed).to_string(),
+                p.best_height.load(Ordering::Relaxed).to_string(),
+                last,
+            ])
+        });
+        let widths = [
+            Constraint::Percentage(55),
+            Constraint::Percentage(15),
+            Constraint::Percentage(10),
+            Constraint::Percentage(20),
+        ];
+        let table = Table::new(rows)
+            .header(
+                Row::new(vec!["Pattern", "Found", "Best", "Last hit"])
+                    .style(Style::default().add_modifier(Modifier::BOLD)),
+            )
+            .block(Block::default().borders(Borders::ALL).title("Patterns"))
+            .widths(&widths);
+        f.render_widget(table, area);
+    }
+
+    fn draw_results<B: Backend>(&mut self, f: &mut Frame<B>, area: Rect) {
+        let results = self.results.0.lock().unwrap();
+        // Keep the selection on the same result as new ones come in on top.
+        if let Some(i) = self.selected.selected() {
+            self.selected.select(Some(i + results.len() - self.shown));
+        }
+        self.shown = results.len();
+
+        let revealed = self.revealed.and_then(|i| results.get(i));
+        let chunks = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints(match revealed {
+                Some(_) => [Constraint::Min(3), Constraint::Length(4)],
+                None => [Constraint::Min(3), Constraint::Length(0)],
+            })
+            .split(area);
+
+        let items: Vec<ListItem> = results
+            .iter()
+            .rev()
+            .map(|found| {
+                ListItem::new(format!(
+                    "{:>12}  {:<39}  height {:<3}  {}",
+                    format!(
+                        "{} ago",
+                        estimate::format_duration(found.at.elapsed().as_secs_f64())
+                    ),
+                    found.address,
+                    found.height,
+                    found.pattern
+                ))
+            })
+            .collect();
+        let list = List::new(items)
+            .block(
+                Block::default()
+                    .borders(Borders::ALL)
+                    .title("Results (Enter shows the key of the selected one)"),
+            )
+            .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+        f.render_stateful_widget(list, chunks[0], &mut self.selected);
+
+        if let Some(found) = revealed {
+            let key = Zeroizing::new(found.private_key.as_slice().encode_hex::<String>());
+            let text = Zeroizing::new(format!("Address: {}\nPrivateKey: {}", found.address, *key));
+            let block = Block::default().borders(Borders::ALL).title("Key");
+            f.render_widget(Paragraph::new(text.as_str()).block(block), chunks[1]);
+        }
+    }
+
+    fn draw_footer<B: Backend>(&self, f: &mut Frame<B>, area: Rect) {
+        let text = match &self.mode {
+            Mode::AddPattern(input) => {
+                format!("Regex to add (Enter adds, Esc cancels): {}_", input)
+            }
+            Mode::Normal if !self.status.is_empty() => self.status.clone(),
+            Mode::Normal => String::from(
+                "q quit | p pause/resume | a add pattern | up/down select | Enter show/hide key",
+            ),
+        };
+        f.render_widget(Paragraph::new(text), area);
+    }
+}
+
+/// The terminal in raw mode on the alternate screen, put back when dropped,
+/// also on errors and panics.
+struct Screen(Terminal<CrosstermBackend<Stdout>>);
+
+impl Screen {
+    fn new() -> io::Result<Self> {
+        terminal::enable_raw_mode()?;
+        let mut stdout = io::stdout();
+        execute!(stdout, EnterAlternateScreen)?;
+        Ok(Self(Terminal::new(CrosstermBackend::new(stdout))?))
+    }
+}
+
+impl Drop for Screen {
+    fn drop(&mut self) {
+        let _ = terminal::disable_raw_mode();
+        let _ = execute!(self.0.backend_mut(), LeaveAlternateScreen);
+        let _ = self.0.show_cursor();
+    }
+}
+
+fn terminal_error(e: io::Error) -> Error {
+    Error::Io(String::from("terminal"), e)
+}
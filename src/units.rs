@@ -0,0 +1,337 @@
+//! Numbered work units and the records showing they were mined.
+//!
+//! A unit is split into slices of [`SLICE_SIZE`] keys. All public keys of a
+//! slice are hashed into its leaf, so a single wrong one changes it, and the
+//! record of a unit carries the Merkle root over its leaves. Anyone
+//! knowing the master secret can recompute a slice on the CPU and check it
+//! against the record with a short proof from the worker.
+
+use std::{fmt, str::FromStr};
+
+use hex::{FromHex, ToHex};
+use rayon::prelude::*;
+use sha2::{Digest as _, Sha256};
+
+use crate::{
+    cpu,
+    handler::leading_zeros_of_pubkey,
+    seeds::{self, MasterSecret, WorkRange},
+    PublicKey,
+};
+
+/// Keys hashed into one leaf.
+pub const SLICE_SIZE: u64 = 1 << 16;
+
+pub type Digest = [u8; 32];
+
+/// Unit `index`, covering counters `range`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WorkUnit {
+    pub index: u64,
+    pub range: WorkRange,
+}
+
+impl WorkUnit {
+    /// Unit `index` of units of `size` keys, `None` past the last counter.
+    pub fn new(index: u64, size: u64) -> Option<Self> {
+        let start = index.checked_mul(size)?;
+        Some(Self {
+            index,
+            range: WorkRange {
+                start,
+                end: start.checked_add(size)?,
+            },
+        })
+    }
+
+    pub fn slices(&self) -> usize {
+        let len = self.range.len();
+        (len / SLICE_SIZE + (len % SLICE_SIZE != 0) as u64) as usize
+    }
+
+    /// Counters of slice `slice`.
+    pub fn slice(&self, slice: usize) -> WorkRange {
+        let start = self.range.start + slice as u64 * SLICE_SIZE;
+        WorkRange {
+            start,
+            end: (start + SLICE_SIZE).min(self.range.end),
+        }
+    }
+}
+
+/// What a worker reports for a finished unit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Completion {
+    pub unit: u64,
+    pub keys: u64,
+    pub best_height: u8,
+    pub root: Digest,
+}
+
+impl fmt::Display for Completion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} {} {} {}",
+            self.unit,
+            self.keys,
+            self.best_height,
+            self.root.encode_hex::<String>()
+        )
+    }
+}
+
+impl FromStr for Completion {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.split(' ');
+        let (Some(unit), Some(keys), Some(best_height), Some(root), None) = (
+            parts.next(),
+            parts.next(),
+            parts.next(),
+            parts.next(),
+            parts.next(),
+        ) else {
+            return Err(format!("malformed completion record {}", s));
+        };
+        Ok(Self {
+            unit: unit.parse().map_err(|e| format!("unit: {}", e))?,
+            keys: keys.parse().map_err(|e| format!("keys: {}", e))?,
+            best_height: best_height.parse().map_err(|e| format!("height: {}", e))?,
+            root: Digest::from_hex(root).map_err(|e| format!("root: {}", e))?,
+        })
+    }
+}
+
+/// Builds the record of a unit from its public keys, given in order.
+pub struct Recorder {
+    unit: WorkUnit,
+    keys: u64,
+    best_height: u8,
+    slice: Sha256,
+    leaves: Vec<Digest>,
+}
+
+impl Recorder {
+    pub fn new(unit: WorkUnit) -> Self {
+        Self {
+            unit,
+            keys: 0,
+            best_height: 0,
+            slice: leaf_hasher(),
+            leaves: Vec::with_capacity(unit.slices()),
+        }
+    }
+
+    pub fn unit(&self) -> &WorkUnit {
+        &self.unit
+    }
+
+    /// Counter of the next key to add.
+    pub fn next(&self) -> u64 {
+        self.unit.range.start + self.keys
+    }
+
+    /// Adds the public keys of the next counters of the unit.
+    pub fn add(&mut self, pubkeys: &[PublicKey]) {
+        if pubkeys.is_empty() {
+            return;
+        }
+        let best = pubkeys
+            .par_iter()
+            .map(|pk| leading_zeros_of_pubkey(pk))
+            .max();
+        self.best_height = self.best_height.max(best.unwrap_or(0));
+
+        // Keys of the slice under way, then whole slices hashed in parallel,
+        // the last of which may be the start of one.
+        let left = (SLICE_SIZE - self.keys % SLICE_SIZE) as usize;
+        let (head, rest) = pubkeys.split_at(left.min(pubkeys.len()));
+        self.slice.update(head.flatten());
+        self.keys += head.len() as u64;
+        self.end_slice();
+        let slices: Vec<(Sha256, usize)> = rest
+            .par_chunks(SLICE_SIZE as usize)
+            .map(|slice| {
+                let mut hasher = leaf_hasher();
+                hasher.update(slice.flatten());
+                (hasher, slice.len())
+            })
+            .collect();
+        for (hasher, len) in slices {
+            self.slice = hasher;
+            self.keys += len as u64;
+            self.end_slice();
+        }
+    }
+
+    /// Turns the slice under way into a leaf once it has all its keys.
+    fn end_slice(&mut self) {
+        if self.keys % SLICE_SIZE == 0 || self.is_complete() {
+            let slice = std::mem::replace(&mut self.slice, leaf_hasher());
+            self.leaves.push(slice.finalize().into());
+        }
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.keys == self.unit.range.len()
+    }
+
+    pub fn completion(&self) -> Completion {
+        Completion {
+            unit: self.unit.index,
+            keys: self.keys,
+            best_height: self.best_height,
+            root: root(&self.leaves),
+        }
+    }
+
+    /// Leaf of slice `slice` and the proof it belongs to the root.
+    pub fn proof(&self, slice: usize) -> Option<(Digest, Vec<Digest>)> {
+        let leaf = *self.leaves.get(slice)?;
+        Some((leaf, path(&self.leaves, slice)))
+    }
+}
+
+fn leaf_hasher() -> Sha256 {
+    let mut hasher = Sha256::new();
+    hasher.update([0u8]);
+    hasher
+}
+
+fn node(left: &Digest, right: &Digest) -> Digest {
+    let mut hasher = Sha256::new();
+    hasher.update([1u8]);
+    hasher.update(left);
+    hasher.update(right);
+    hasher.finalize().into()
+}
+
+/// Hashes pairs of a level, an odd last node moving up as is.
+fn level_up(level: &[Digest]) -> Vec<Digest> {
+    level
+        .chunks(2)
+        .map(|pair| match pair {
+            [left, right] => node(left, right),
+            [single] => *single,
+            _ => unreachable!(),
+        })
+        .collect()
+}
+
+pub fn root(leaves: &[Digest]) -> Digest {
+    let mut level = leaves.to_vec();
+    while level.len() > 1 {
+        level = level_up(&level);
+    }
+    level.first().copied().unwrap_or_default()
+}
+
+/// Siblings on the way from leaf `index` to the root.
+pub fn path(leaves: &[Digest], mut index: usize) -> Vec<Digest> {
+    let mut level = leaves.to_vec();
+    let mut path = Vec::new();
+    while level.len() > 1 {
+        if let Some(sibling) = level.get(index ^ 1) {
+            path.push(*sibling);
+        }
+        level = level_up(&level);
+        index /= 2;
+    }
+    path
+}
+
+/// Whether `leaf` is leaf `index` of `count` under `root`.
+pub fn verify(
+    root: &Digest,
+    count: usize,
+    mut index: usize,
+    leaf: &Digest,
+    path: &[Digest],
+) -> bool {
+    if index >= count {
+        return false;
+    }
+    let (mut hash, mut count, mut path) = (*leaf, count, path.iter());
+    while count > 1 {
+        if index ^ 1 < count {
+            let Some(sibling) = path.next() else {
+                return false;
+            };
+            hash = match index % 2 {
+                0 => node(&hash, sibling),
+                _ => node(sibling, &hash),
+            };
+        }
+        index /= 2;
+        count = (count + 1) / 2;
+    }
+    path.next().is_none() && hash == *root
+}
+
+/// Recomputes the leaf and best height of a slice on the CPU.
+pub fn recompute(secret: &MasterSecret, unit: &WorkUnit, slice: usize) -> (Digest, u8) {
+    let range = unit.slice(slice);
+    let pubkeys: Vec<PublicKey> = (range.start..range.end)
+        .into_par_iter()
+        .map(|counter| cpu::dalek_public_from_seed(&seeds::derive(secret, counter)))
+        .collect();
+    let mut hasher = leaf_hasher();
+    hasher.update(pubkeys.flatten());
+    let best = pubkeys.iter().map(|pk| leading_zeros_of_pubkey(pk)).max();
+    (hasher.finalize().into(), best.unwrap_or(0))
+}
+
+#[cfg(test)]
+mod tests {
+    use rayon::prelude::*;
+
+    use super::{path, recompute, root, verify, Completion, Recorder, WorkUnit, SLICE_SIZE};
+    use crate::{cpu, seeds, PublicKey};
+
+    #[test]
+    fn test_merkle() {
+        for count in 1..10 {
+            let leaves: Vec<_> = (0..count).map(|i| [i as u8; 32]).collect();
+            let root = root(&leaves);
+            for (i, leaf) in leaves.iter().enumerate() {
+                let path = path(&leaves, i);
+                assert!(verify(&root, count, i, leaf, &path));
+                assert!(!verify(&root, count, i, &[99; 32], &path));
+                assert!(!verify(&root, count, (i + 1) % count, leaf, &path) || count == 1);
+            }
+        }
+    }
+
+    #[test]
+    fn test_recorder() {
+        let secret = [3u8; 32];
+        let unit = WorkUnit::new(2, SLICE_SIZE + 100).unwrap();
+        assert_eq!(unit.slices(), 2);
+        let pubkeys: Vec<PublicKey> = (unit.range.start..unit.range.end)
+            .into_par_iter()
+            .map(|counter| cpu::dalek_public_from_seed(&seeds::derive(&secret, counter)))
+            .collect();
+
+        let mut recorder = Recorder::new(unit);
+        for batch in pubkeys.chunks(30_000) {
+            assert!(!recorder.is_complete());
+            recorder.add(batch);
+        }
+        assert!(recorder.is_complete());
+        let completion = recorder.completion();
+        assert_eq!(completion.keys, SLICE_SIZE + 100);
+        assert_eq!(completion.to_string().parse::<Completion>(), Ok(completion));
+
+        let mut best = 0;
+        for slice in 0..unit.slices() {
+            let (leaf, height) = recompute(&secret, &unit, slice);
+            let (proven, path) = recorder.proof(slice).unwrap();
+            assert_eq!(leaf, proven);
+            assert!(verify(&completion.root, unit.slices(), slice, &leaf, &path));
+            best = best.max(height);
+        }
+        assert_eq!(best, completion.best_height);
+    }
+}